@@ -0,0 +1,52 @@
+//! Records the RPC traffic of a wallet scan into a cassette for the replay
+//! tests:
+//!
+//! ```text
+//! cargo run --example record_wallet -- <rpc url> <owner> [cassette]
+//! ```
+//!
+//! The cassette defaults to `tests/fixtures/wallet.jsonl`. Mint lookups are
+//! recorded for the first NFT of the wallet.
+
+use solana_e::crawler::SolanaCrawler;
+use solana_e::rpc::cassette::RecordingSender;
+use solana_e::rpc::client::SolanaClient;
+use solana_e::rpc::custom_http_sender::CustomHttpSender;
+use std::error::Error;
+
+const DEFAULT_CASSETTE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/wallet.jsonl");
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let (url, owner) = match (args.next(), args.next()) {
+        (Some(url), Some(owner)) => (url, owner),
+        _ => return Err("usage: record_wallet <rpc url> <owner> [cassette]".into()),
+    };
+    let cassette = args.next().unwrap_or_else(|| DEFAULT_CASSETTE.to_string());
+
+    let sender = RecordingSender::create(CustomHttpSender::new(&url), &cassette)?;
+    let crawler = SolanaCrawler::new_client(SolanaClient::new_sender(sender));
+
+    println!("node {}", crawler.get_version().await?);
+    let nfts = crawler.get_nfts_for_owner(&owner).await?;
+    for token in &nfts.tokens {
+        println!("nft {} {} {:?}", token.mint, token.name, token.class);
+    }
+    let portfolio = crawler.get_token_balances_for_owner(&owner).await?;
+    println!("{} SOL", portfolio.sol());
+    for balance in &portfolio.tokens {
+        println!("token {} {}", balance.mint, balance.ui_amount_string);
+    }
+    for warning in nfts.warnings.iter().chain(portfolio.warnings.iter()) {
+        println!("warning {}", warning);
+    }
+
+    if let Some(token) = nfts.tokens.first() {
+        crawler.get_mint(&token.mint).await?;
+        crawler.get_token_metadata(&token.mint).await?;
+    }
+
+    println!("recorded {}", cassette);
+    Ok(())
+}
@@ -1,159 +1,2041 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
+use crate::error::{CrawlerError, Result};
+use crate::rpc::batch::BatchRequest;
 use crate::rpc::client::SolanaClient;
+use crate::rpc::das;
+use crate::rpc::pubsub::{PubsubConfig, PubsubEvent, PubsubSubscription, Subscription};
 use borsh::BorshDeserialize;
-use mpl_token_metadata::pda::find_metadata_account;
-use mpl_token_metadata::state::Metadata;
-use serde::Serialize;
+use futures::stream::{self, StreamExt};
+use mpl_token_metadata::pda::{find_master_edition_account, find_metadata_account};
+use mpl_token_metadata::state::{Edition, Key, MasterEditionV2, Metadata};
+use serde::de::{DeserializeOwned, IntoDeserializer};
+use serde::{Deserialize, Deserializer, Serialize};
 use solana_account_decoder::parse_account_data::{ParsableAccount, PARSABLE_PROGRAM_IDS};
-use solana_account_decoder::parse_token::{TokenAccountType, UiTokenAmount};
-use solana_client::client_error::{ClientError, ClientErrorKind, Result as ClientResult};
-use solana_client::rpc_request::{RpcRequest, TokenAccountsFilter};
+use solana_account_decoder::parse_token::{TokenAccountType, UiAccountState, UiTokenAccount};
+use solana_account_decoder::{UiAccount, UiAccountData, UiAccountEncoding};
+use solana_client::client_error::{self, ClientError};
+use solana_client::rpc_config::RpcAccountInfoConfig;
+use solana_client::rpc_request::{RpcError, RpcRequest, MAX_MULTIPLE_ACCOUNTS};
+use solana_client::rpc_response::{Response, RpcKeyedAccount};
+use solana_sdk::account::Account;
 use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::native_token::lamports_to_sol;
 use solana_sdk::pubkey::Pubkey;
+use spl_token_2022::extension::metadata_pointer::MetadataPointer;
+use spl_token_2022::extension::{BaseStateWithExtensions, StateWithExtensions};
+use spl_token_2022::state::Mint;
+use spl_token_metadata_interface::state::TokenMetadata as TokenMetadataExtension;
+use spl_type_length_value::state::{TlvState, TlvStateBorrowed};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use utoipa::{IntoParams, ToSchema};
 
-#[derive(Serialize, Clone, PartialEq, Eq)]
+/// Number of `getMultipleAccounts` chunks kept in flight at once.
+const MULTIPLE_ACCOUNTS_CONCURRENCY: usize = 4;
+
+/// Number of off-chain metadata documents fetched at once.
+const OFF_CHAIN_CONCURRENCY: usize = 8;
+
+const OFF_CHAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Default, ToSchema)]
 pub struct TokenMetadata {
     pub update_authority: String,
     pub mint: String,
     pub name: String,
     pub symbol: String,
     pub uri: String,
+    pub compressed: bool,
+    pub seller_fee_basis_points: u16,
+    pub creators: Vec<TokenCreator>,
+    pub collection: Option<TokenCollection>,
+    pub primary_sale_happened: bool,
+    pub is_mutable: bool,
+    pub edition_nonce: Option<u8>,
+    pub token_standard: Option<String>,
+    pub uses: Option<TokenUses>,
+    pub edition: Option<TokenEdition>,
+    /// Raw amount held by the wallet.
+    pub amount: u64,
+    /// Total supply of the mint.
+    pub supply: u64,
+    pub class: Option<TokenClass>,
+}
+
+impl TokenMetadata {
+    /// Off-chain metadata uri, `uri` being wrapped for `/load_metadata`.
+    pub fn metadata_uri(&self) -> Option<String> {
+        decode_uri(&self.uri)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenClass {
+    /// One of one, backed by a master or print edition.
+    Nft,
+    /// An NFT with the programmable token standard.
+    ProgrammableNft,
+    /// Semi-fungible: no decimals but either more than one token in
+    /// circulation or no edition account.
+    Sft,
+}
+
+/// Which token classes `get_nfts_for_owner` returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClassificationPolicy {
+    pub nft: bool,
+    pub programmable_nft: bool,
+    pub sft: bool,
+}
+
+impl ClassificationPolicy {
+    /// Only plain NFTs.
+    pub fn strict() -> Self {
+        Self {
+            nft: true,
+            programmable_nft: false,
+            sft: false,
+        }
+    }
+
+    /// Every token without decimals.
+    pub fn all() -> Self {
+        Self {
+            nft: true,
+            programmable_nft: true,
+            sft: true,
+        }
+    }
+
+    pub fn allows(&self, class: TokenClass) -> bool {
+        match class {
+            TokenClass::Nft => self.nft,
+            TokenClass::ProgrammableNft => self.programmable_nft,
+            TokenClass::Sft => self.sft,
+        }
+    }
+}
+
+impl Default for ClassificationPolicy {
+    fn default() -> Self {
+        Self {
+            nft: true,
+            programmable_nft: true,
+            sft: false,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct TokenCreator {
+    pub address: String,
+    pub verified: bool,
+    /// Percentage of royalties, not basis points.
+    pub share: u8,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct TokenCollection {
+    pub key: String,
+    pub verified: bool,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct TokenUses {
+    pub use_method: String,
+    pub remaining: u64,
+    pub total: u64,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EditionKind {
+    Master,
+    Print,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct TokenEdition {
+    pub kind: EditionKind,
+    /// Print number, `None` for master editions.
+    pub number: Option<u64>,
+    /// Prints minted so far from the master edition.
+    pub supply: Option<u64>,
+    /// `None` when the master edition has unlimited supply.
+    pub max_supply: Option<u64>,
+    /// Master edition account a print was minted from.
+    pub parent: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupKind {
+    /// Tokens sharing a verified collection mint.
+    Collection,
+    /// Legacy tokens without a verified collection, grouped by update
+    /// authority.
+    UpdateAuthority,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CollectionGroup {
+    pub kind: GroupKind,
+    /// Collection mint or update authority, depending on `kind`.
+    pub key: String,
+    pub name: Option<String>,
+    pub image: Option<String>,
+    pub count: usize,
+    pub tokens: Vec<TokenMetadata>,
+}
+
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct TokenBalance {
+    /// Token account holding the balance.
+    pub account: String,
+    pub mint: String,
+    /// Raw amount in base units.
+    pub amount: String,
+    pub decimals: u8,
+    pub ui_amount: Option<f64>,
+    pub ui_amount_string: String,
+    pub delegate: Option<String>,
+    pub frozen: bool,
+    /// Metaplex or Token-2022 metadata of the mint, when it has any.
+    pub metadata: Option<TokenMetadata>,
+}
+
+/// Fungible balances of a wallet, see `get_token_balances_for_owner`.
+#[derive(Default)]
+pub struct Portfolio {
+    pub lamports: u64,
+    pub tokens: Vec<TokenBalance>,
+    pub warnings: Vec<CrawlerError>,
+}
+
+impl Portfolio {
+    pub fn sol(&self) -> f64 {
+        lamports_to_sol(self.lamports)
+    }
+}
+
+/// Holdings of a wallet, see `get_wallet`. `nfts` are the tokens
+/// `get_nfts_for_owner` lists, everything else is in `portfolio`, whose
+/// `warnings` cover the whole scan.
+#[derive(Default)]
+pub struct Wallet {
+    pub nfts: Vec<TokenMetadata>,
+    pub portfolio: Portfolio,
 }
 
+/// Result of a wallet scan. Tokens that could not be decoded are reported
+/// in `warnings` instead of failing the whole scan.
+#[derive(Default)]
+pub struct WalletNfts {
+    pub tokens: Vec<TokenMetadata>,
+    pub warnings: Vec<CrawlerError>,
+}
+
+/// Field NFT listings are sorted by, see `NftQuery`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NftSort {
+    /// Case-insensitive.
+    Name,
+    /// Case-insensitive, then by name.
+    Symbol,
+    /// Collection key, tokens without one last, then by name.
+    Collection,
+    Mint,
+}
+
+impl NftSort {
+    fn compare(&self, a: &TokenMetadata, b: &TokenMetadata) -> Ordering {
+        let by_name = || a.name.to_lowercase().cmp(&b.name.to_lowercase());
+        match self {
+            NftSort::Name => by_name(),
+            NftSort::Symbol => a
+                .symbol
+                .to_lowercase()
+                .cmp(&b.symbol.to_lowercase())
+                .then_with(by_name),
+            NftSort::Collection => match (&a.collection, &b.collection) {
+                (Some(a), Some(b)) => a.key.cmp(&b.key),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            }
+            .then_with(by_name),
+            NftSort::Mint => Ordering::Equal,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Filters, order and page of an NFT listing. Filters are exact matches and
+/// all have to match, empty ones are ignored.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, IntoParams)]
+#[serde(default)]
+#[into_params(parameter_in = Query)]
+pub struct NftQuery {
+    /// Verified collection mint.
+    pub collection: Option<String>,
+    /// Any of the creators.
+    pub creator: Option<String>,
+    pub update_authority: Option<String>,
+    /// Case-insensitive.
+    pub symbol: Option<String>,
+    /// Scan order when unset or empty.
+    #[serde(deserialize_with = "empty_as_none")]
+    pub sort: Option<NftSort>,
+    pub order: SortOrder,
+    pub offset: usize,
+    /// Page size, 50 when unset and at most 500.
+    pub limit: Option<usize>,
+}
+
+/// One page of an NFT listing, see `NftQuery::apply`.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct NftPage<T = TokenMetadata> {
+    pub items: Vec<T>,
+    /// Items matching the filters, across all pages.
+    pub total: usize,
+    pub offset: usize,
+    /// Offset of the next page, if any.
+    pub next_offset: Option<usize>,
+}
+
+impl NftQuery {
+    pub fn matches(&self, token: &TokenMetadata) -> bool {
+        let filter = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|x| !x.is_empty())
+                .map(str::to_string)
+        };
+
+        if let Some(collection) = filter(&self.collection) {
+            if token.collection.as_ref().map(|x| &x.key) != Some(&collection) {
+                return false;
+            }
+        }
+        if let Some(creator) = filter(&self.creator) {
+            if !token.creators.iter().any(|x| x.address == creator) {
+                return false;
+            }
+        }
+        if let Some(update_authority) = filter(&self.update_authority) {
+            if token.update_authority != update_authority {
+                return false;
+            }
+        }
+        if let Some(symbol) = filter(&self.symbol) {
+            if !token.symbol.eq_ignore_ascii_case(&symbol) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Filters and sorts `tokens`, then cuts out the requested page.
+    pub fn apply(&self, tokens: Vec<TokenMetadata>) -> NftPage {
+        self.page(self.select(tokens))
+    }
+
+    /// Filters and sorts `tokens` without paging them.
+    pub fn select(&self, mut tokens: Vec<TokenMetadata>) -> Vec<TokenMetadata> {
+        tokens.retain(|token| self.matches(token));
+        if let Some(sort) = self.sort {
+            // Ties are broken by mint so that pages are stable.
+            tokens.sort_by(|a, b| {
+                let ordering = sort.compare(a, b).then_with(|| a.mint.cmp(&b.mint));
+                match self.order {
+                    SortOrder::Asc => ordering,
+                    SortOrder::Desc => ordering.reverse(),
+                }
+            });
+        }
+        tokens
+    }
+
+    /// Cuts the requested page out of `items`, which may be tokens or
+    /// anything built from them such as collection groups.
+    pub fn page<T>(&self, mut items: Vec<T>) -> NftPage<T> {
+        let total = items.len();
+        let offset = self.offset.min(total);
+        let end = match self.limit {
+            Some(limit) => offset.saturating_add(limit).min(total),
+            None => total,
+        };
+        NftPage {
+            items: items.drain(offset..end).collect(),
+            total,
+            offset,
+            next_offset: (end < total).then_some(end),
+        }
+    }
+}
+
+/// Reads an empty value, as submitted by a form's blank option, as unset.
+fn empty_as_none<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    match <Option<String> as Deserialize>::deserialize(deserializer)?.as_deref() {
+        None | Some("") => Ok(None),
+        Some(value) => T::deserialize(value.into_deserializer()).map(Some),
+    }
+}
+
+/// On-chain state of a mint, see `get_mint`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct MintAccount {
+    pub mint: String,
+    /// Token program owning the mint.
+    pub program: String,
+    pub supply: u64,
+    pub decimals: u8,
+    pub mint_authority: Option<String>,
+    pub freeze_authority: Option<String>,
+    /// Account holding the metadata, the mint itself when it embeds it.
+    pub metadata_address: String,
+}
+
+/// Change in the NFTs held by a watched wallet, see `watch_nfts`.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WalletEvent {
+    Gained(TokenMetadata),
+    /// Carries the metadata last seen for the token.
+    Lost(TokenMetadata),
+}
+
+/// Live NFT changes of a wallet. Dropping it stops watching.
+pub struct WalletWatch {
+    events: mpsc::Receiver<WalletEvent>,
+    task: JoinHandle<()>,
+}
+
+impl WalletWatch {
+    pub async fn recv(&mut self) -> Option<WalletEvent> {
+        self.events.recv().await
+    }
+}
+
+impl Drop for WalletWatch {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+#[derive(Clone)]
 pub struct SolanaCrawler {
     client: Arc<SolanaClient>,
+    http: reqwest::Client,
+    policy: ClassificationPolicy,
 }
 
 impl SolanaCrawler {
     pub fn new<U: ToString>(url: U) -> Self {
-        let client = SolanaClient::new(url);
+        Self::new_client(SolanaClient::new(url))
+    }
+
+    /// Crawler that fails over between several RPC endpoints.
+    pub fn new_pool<U: ToString>(urls: &[U]) -> Self {
+        Self::new_client(SolanaClient::new_pool(urls))
+    }
+
+    pub fn new_client(client: SolanaClient) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(OFF_CHAIN_TIMEOUT)
+            .build()
+            .expect("build off-chain metadata client");
+
         Self {
             client: Arc::new(client),
+            http,
+            policy: ClassificationPolicy::default(),
         }
     }
 
-    pub async fn get_version(&self) -> ClientResult<String> {
-        let client = self.client.clone();
-        let result = actix_web::rt::task::spawn_blocking(move || client.get_version())
-            .await
-            .map_err(|_| ClientError {
-                request: None,
-                kind: ClientErrorKind::Custom(
-                    "get_version: fatal error in spawning blocking thread".into(),
-                ),
-            })?;
+    pub fn with_policy(mut self, policy: ClassificationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
 
-        let result = result.map(|x| x.solana_core);
-        result
+    pub fn policy(&self) -> ClassificationPolicy {
+        self.policy
     }
 
-    pub async fn get_nfts_for_owner(&self, addr: &str) -> ClientResult<Vec<TokenMetadata>> {
-        let account = Pubkey::from_str(addr).map_err(|_| ClientError {
-            request: None,
-            kind: ClientErrorKind::Custom(
-                "get_nfts_for_owner: fatal error in validating owner address".into(),
-            ),
-        })?;
-
-        let client = self.client.clone();
-        let result = actix_web::rt::task::spawn_blocking(move || {
-            let output = client
-                .get_token_accounts_by_owner(
-                    &account,
-                    TokenAccountsFilter::ProgramId(spl_token::id()),
-                )
-                .expect("msg");
-
-            let mut nfts: Vec<TokenMetadata> = vec![];
-            for i in output.iter() {
-                let program_owner = Pubkey::from_str(i.account.owner.as_str()).unwrap();
-                if !spl_token::check_id(&program_owner) {
+    /// Whether `get_nfts_for_owner` lists a classified token.
+    fn is_listed(&self, token: &TokenMetadata) -> bool {
+        token.class.is_some_and(|class| self.policy.allows(class))
+    }
+
+    pub async fn get_version(&self) -> Result<String> {
+        let version = self.client.get_version().await?;
+        Ok(version.solana_core)
+    }
+
+    pub async fn get_nfts_for_owner(&self, addr: &str) -> Result<WalletNfts> {
+        let owner = parse_pubkey(addr)?;
+        let (legacy_accounts, token_2022_accounts, _) = self.get_owner_accounts(&owner).await?;
+
+        let mut scan = WalletNfts::default();
+        let mut candidates = vec![];
+        for keyed_account in legacy_accounts.iter().chain(token_2022_accounts.iter()) {
+            match nft_candidate_for_account(&keyed_account.account) {
+                Ok(Some(candidate)) => candidates.push(candidate),
+                Ok(None) => continue,
+                Err(err) => {
+                    log::warn!("skipping token account {}: {}", keyed_account.pubkey, err);
+                    scan.warnings.push(err);
+                }
+            }
+        }
+
+        let tokens = self.load_tokens(&candidates, &mut scan.warnings).await?;
+        scan.tokens = tokens.into_iter().flatten().collect();
+
+        scan.tokens.retain(|token| self.is_listed(token));
+
+        Ok(scan)
+    }
+
+    pub async fn get_mint(&self, addr: &str) -> Result<MintAccount> {
+        let mint = parse_pubkey(addr)?;
+        let (program, info) = self.get_mint_info(&mint).await?;
+
+        let metadata_address = match &info.metadata {
+            MetadataLocation::Account(address) => *address,
+            MetadataLocation::Embedded(_) => mint,
+        };
+        Ok(MintAccount {
+            mint: mint.to_string(),
+            program: program.to_string(),
+            supply: info.supply,
+            decimals: info.decimals,
+            mint_authority: info.mint_authority.map(|x| x.to_string()),
+            freeze_authority: info.freeze_authority.map(|x| x.to_string()),
+            metadata_address: metadata_address.to_string(),
+        })
+    }
+
+    /// Metadata of the mint `addr`, with its edition and class resolved. The
+    /// class policy doesn't apply, fungible tokens are returned too, and
+    /// `amount` is left at zero.
+    pub async fn get_token_metadata(&self, addr: &str) -> Result<TokenMetadata> {
+        let mint = parse_pubkey(addr)?;
+        let (_, info) = self.get_mint_info(&mint).await?;
+
+        let token = match info.metadata {
+            MetadataLocation::Embedded(token) => *token,
+            MetadataLocation::Account(address) => {
+                let account = self
+                    .get_multiple_accounts(&[address])
+                    .await?
+                    .pop()
+                    .flatten()
+                    .ok_or_else(|| CrawlerError::AccountNotFound(address.to_string()))?;
+                decode_metadata_account(&mint, &account)?
+            }
+        };
+
+        let mut scan = WalletNfts {
+            tokens: vec![TokenMetadata {
+                supply: info.supply,
+                ..token
+            }],
+            ..Default::default()
+        };
+        self.resolve_editions(&mut scan).await?;
+        if let Some(err) = scan.warnings.pop() {
+            return Err(err);
+        }
+
+        // There is no holder here, so the mint is classified as if its whole
+        // supply was held. Tokens with decimals are left unclassified.
+        let mut token = scan.tokens.remove(0);
+        if info.decimals == 0 {
+            token.class = classify(
+                &TokenMetadata {
+                    amount: token.supply,
+                    ..token.clone()
+                },
+                info.mint_authority.is_none(),
+            );
+        }
+        Ok(token)
+    }
+
+    /// Scans the NFTs of `addr`, then watches its token accounts over the
+    /// websocket at `ws_url` and reports the NFTs it gains or loses.
+    ///
+    /// Notifications only say that something changed, so the wallet is
+    /// rescanned and diffed against the previous scan whenever one of its
+    /// zero-decimal token accounts changes, its lamports change or the
+    /// connection is re-established. The lamports cover token accounts
+    /// closed in the same transaction that emptied them, which no longer
+    /// match the owner filter.
+    pub async fn watch_nfts(&self, ws_url: &str, addr: &str) -> Result<(WalletNfts, WalletWatch)> {
+        let owner = parse_pubkey(addr)?;
+        let nfts = self.get_nfts_for_owner(addr).await?;
+        let mut held: HashMap<String, TokenMetadata> = nfts
+            .tokens
+            .iter()
+            .map(|token| (token.mint.clone(), token.clone()))
+            .collect();
+
+        let subscriptions = vec![
+            Subscription::Account(owner),
+            Subscription::ProgramOwner {
+                program: spl_token::id(),
+                owner,
+            },
+            Subscription::ProgramOwner {
+                program: spl_token_2022::id(),
+                owner,
+            },
+        ];
+        let mut subscription =
+            PubsubSubscription::new(ws_url, subscriptions, PubsubConfig::default());
+
+        let (sender, events) = mpsc::channel(64);
+        let crawler = self.clone();
+        let addr = addr.to_string();
+        let task = tokio::spawn(async move {
+            while let Some(event) = subscription.recv().await {
+                if !may_change_nfts(&event) {
                     continue;
                 }
+                // Bursts of notifications from one transaction share a scan.
+                while subscription.try_recv().is_some() {}
 
-                let program_name = PARSABLE_PROGRAM_IDS.get(&program_owner).unwrap();
-                let data = match &i.account.data {
-                    solana_account_decoder::UiAccountData::Json(data) => Some(data.clone()),
-                    _ => None,
+                let tokens = match crawler.get_nfts_for_owner(&addr).await {
+                    Ok(scan) => scan.tokens,
+                    Err(err) => {
+                        log::warn!("rescanning {}: {}", addr, err);
+                        continue;
+                    }
                 };
+                for event in diff_nfts(&mut held, tokens) {
+                    if sender.send(event).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok((nfts, WalletWatch { events, task }))
+    }
 
-                if let Some(d) = data {
-                    match program_name {
-                        ParsableAccount::SplToken | ParsableAccount::SplToken2022 => {
-                            let tmp = serde_json::from_value::<TokenAccountType>(d.parsed).unwrap();
-                            match tmp {
-                                TokenAccountType::Account(t) => {
-                                    let UiTokenAmount {
-                                        ui_amount,
-                                        decimals,
-                                        ..
-                                    } = t.token_amount;
-
-                                    if let Some(ui_amount_absolute) = ui_amount {
-                                        let amount = spl_token::ui_amount_to_amount(
-                                            ui_amount_absolute,
-                                            decimals,
-                                        );
-
-                                        if amount == 1 {
-                                            let mint_account = Pubkey::from_str(t.mint.as_str())
-                                                .expect("msg");
-                                            let (metadata_account, _) = find_metadata_account(&mint_account);
-                                            let info = client.get_account_with_commitment(&metadata_account, CommitmentConfig::confirmed())
-                                                .expect("msg");
-
-                                            if info.value.is_none() {
-                                                continue;
-                                            }
-
-                                            let account_data = info.value
-                                                .ok_or(ClientError {
-                                                    request: Some(RpcRequest::GetAccountInfo),
-                                                    kind: ClientErrorKind::Custom(
-                                                        "get_nfts_for_owner: fatal error retrieving account data".into(),
-                                                    ),
-                                                })
-                                                .expect("msg");
-
-                                            let mut sliced_data = account_data.data.as_slice();
-                                            let meta = Metadata::deserialize(&mut sliced_data).unwrap();
-
-                                            let uri = Some(meta.data.uri.trim_end_matches("\0").to_string());
-                                            let uri = bincode::serialize(&uri)
-                                                .expect("msg");
-
-                                            let safe_uri = base64::encode(uri);
-
-                                            nfts.push(TokenMetadata {
-                                                update_authority: meta.update_authority.to_string(),
-                                                mint: meta.mint.to_string(),
-                                                name: meta.data.name.trim_end_matches("\0").to_string(),
-                                                symbol: meta.data.symbol.trim_end_matches("\0").to_string(),
-                                                uri: safe_uri,
-                                            });
-                                        }
-                                    }
-                                }
-                                _ => panic!(),
-                            }
-                        }
-                        _ => panic!(),
-                    };
+    /// Lists every fungible balance held by `addr` together with its native
+    /// SOL balance. Tokens listed by `get_nfts_for_owner` under the crawler's
+    /// policy are left out, token accounts that can't be decoded are
+    /// reported in `warnings`.
+    pub async fn get_token_balances_for_owner(&self, addr: &str) -> Result<Portfolio> {
+        Ok(self.get_wallet(addr).await?.portfolio)
+    }
+
+    /// Both the NFTs and the portfolio of `addr` from a single scan of its
+    /// token accounts, for pages showing both.
+    pub async fn get_wallet(&self, addr: &str) -> Result<Wallet> {
+        let owner = parse_pubkey(addr)?;
+        let (legacy_accounts, token_2022_accounts, lamports) =
+            self.get_owner_accounts(&owner).await?;
+
+        let mut portfolio = Portfolio {
+            lamports,
+            ..Default::default()
+        };
+        let mut holdings = vec![];
+        let mut balances = vec![];
+        for keyed_account in legacy_accounts.iter().chain(token_2022_accounts.iter()) {
+            let holding = parse_token_account(&keyed_account.account)
+                .and_then(|token_account| Ok((token_holding(&token_account)?, token_account)));
+            let ((mint, amount), token_account) = match holding {
+                Ok(holding) => holding,
+                Err(err) => {
+                    log::warn!("skipping token account {}: {}", keyed_account.pubkey, err);
+                    portfolio.warnings.push(err);
+                    continue;
                 }
+            };
+
+            let UiTokenAccount {
+                token_amount,
+                delegate,
+                state,
+                ..
+            } = token_account;
+            if amount == 0 {
+                continue;
             }
 
-            nfts
-        })
-        .await
-        .map_err(|_| ClientError {
-            request: None,
-            kind: ClientErrorKind::Custom(
-                "get_nfts_for_owner: fatal error in spawning blocking thread".into(),
+            holdings.push((mint, amount));
+            balances.push(TokenBalance {
+                account: keyed_account.pubkey.clone(),
+                mint: mint.to_string(),
+                amount: token_amount.amount,
+                decimals: token_amount.decimals,
+                ui_amount: token_amount.ui_amount,
+                ui_amount_string: token_amount.ui_amount_string,
+                delegate,
+                frozen: state == UiAccountState::Frozen,
+                metadata: None,
+            });
+        }
+
+        let mut nfts = vec![];
+        let tokens = self.load_tokens(&holdings, &mut portfolio.warnings).await?;
+        for (balance, metadata) in balances.into_iter().zip(tokens) {
+            match metadata {
+                Some(token) if self.is_listed(&token) => nfts.push(token),
+                metadata => portfolio.tokens.push(TokenBalance {
+                    metadata,
+                    ..balance
+                }),
+            }
+        }
+
+        Ok(Wallet { nfts, portfolio })
+    }
+
+    /// Lists compressed (Bubblegum) NFTs through the DAS `getAssetsByOwner`
+    /// method. Requires an RPC endpoint that implements the DAS api.
+    pub async fn get_compressed_nfts_for_owner(&self, addr: &str) -> Result<WalletNfts> {
+        let owner = parse_pubkey(addr)?;
+
+        let mut scan = WalletNfts::default();
+        let mut page = 1;
+        loop {
+            let params = serde_json::json!({
+                "ownerAddress": owner.to_string(),
+                "page": page,
+                "limit": das::MAX_PAGE_LIMIT,
+            });
+            let result = self
+                .client
+                .sender()
+                .send(das::GET_ASSETS_BY_OWNER, params)
+                .await?;
+            let assets = serde_json::from_value::<das::AssetList>(result).map_err(|err| {
+                CrawlerError::RpcResponse(Box::new(ClientError::new_with_request(
+                    err.into(),
+                    das::GET_ASSETS_BY_OWNER,
+                )))
+            })?;
+
+            let page_len = assets.items.len() as u64;
+            for asset in assets.items {
+                if !asset.is_compressed() || asset.burnt {
+                    continue;
+                }
+
+                match token_metadata_from_asset(asset) {
+                    Ok(token) => scan.tokens.push(token),
+                    Err(err) => {
+                        log::warn!("skipping compressed asset: {}", err);
+                        scan.warnings.push(err);
+                    }
+                }
+            }
+
+            if page_len < assets.limit || page_len == 0 {
+                break;
+            }
+            page += 1;
+        }
+
+        Ok(scan)
+    }
+
+    /// Groups tokens by verified collection mint, falling back to the update
+    /// authority for tokens without one. Collection names and images are
+    /// taken from the collection NFT itself when it can be resolved.
+    pub async fn group_by_collection(
+        &self,
+        tokens: Vec<TokenMetadata>,
+    ) -> Result<Vec<CollectionGroup>> {
+        let mut groups: Vec<CollectionGroup> = vec![];
+        for token in tokens {
+            let (kind, key) = match &token.collection {
+                Some(collection) if collection.verified => {
+                    (GroupKind::Collection, collection.key.clone())
+                }
+                _ => (GroupKind::UpdateAuthority, token.update_authority.clone()),
+            };
+
+            match groups.iter_mut().find(|x| x.kind == kind && x.key == key) {
+                Some(group) => group.tokens.push(token),
+                None => groups.push(CollectionGroup {
+                    kind,
+                    key,
+                    name: None,
+                    image: None,
+                    count: 0,
+                    tokens: vec![token],
+                }),
+            }
+        }
+
+        let collection_mints: Vec<Pubkey> = groups
+            .iter()
+            .filter(|x| x.kind == GroupKind::Collection)
+            .map(|x| parse_pubkey(&x.key))
+            .collect::<Result<_>>()?;
+        let metadata_accounts: Vec<Pubkey> = collection_mints
+            .iter()
+            .map(|mint| find_metadata_account(mint).0)
+            .collect();
+        let accounts = self.get_multiple_accounts(&metadata_accounts).await?;
+
+        let mut collections = vec![];
+        for (mint, account) in collection_mints.iter().zip(accounts) {
+            let collection = account.and_then(|account| decode_metadata(mint, &account.data).ok());
+            collections.push(collection);
+        }
+
+        let images = stream::iter(collections.iter())
+            .map(|collection| async move {
+                match collection {
+                    Some(collection) => self.get_off_chain_image(&collection.uri).await,
+                    None => None,
+                }
+            })
+            .buffered(OFF_CHAIN_CONCURRENCY)
+            .collect::<Vec<_>>()
+            .await;
+
+        let resolved = groups
+            .iter_mut()
+            .filter(|x| x.kind == GroupKind::Collection)
+            .zip(collections.into_iter().zip(images));
+        for (group, (collection, image)) in resolved {
+            group.name = collection.map(|x| x.name);
+            group.image = image;
+        }
+
+        for group in groups.iter_mut() {
+            group.count = group.tokens.len();
+        }
+        groups.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+
+        Ok(groups)
+    }
+
+    /// Fetches the `image` field of the off-chain json document behind an
+    /// encoded uri. Failures are logged and treated as a missing image.
+    async fn get_off_chain_image(&self, encoded_uri: &str) -> Option<String> {
+        let uri = decode_uri(encoded_uri)?;
+        let json = self
+            .http
+            .get(&uri)
+            .send()
+            .await
+            .and_then(|x| x.error_for_status());
+        let json = match json {
+            Ok(response) => response.json::<serde_json::Value>().await,
+            Err(err) => Err(err),
+        };
+
+        match json {
+            Ok(json) => json["image"].as_str().map(|x| x.to_string()),
+            Err(err) => {
+                log::debug!("failed to load off-chain metadata {}: {}", uri, err);
+                None
+            }
+        }
+    }
+
+    /// Fills `TokenMetadata::edition` from the edition PDA of each mint. Print
+    /// editions take their supply from the parent master edition.
+    async fn resolve_editions(&self, scan: &mut WalletNfts) -> Result<()> {
+        let mut edition_accounts = Vec::with_capacity(scan.tokens.len());
+        for token in scan.tokens.iter() {
+            let mint = parse_pubkey(&token.mint)?;
+            edition_accounts.push(find_master_edition_account(&mint).0);
+        }
+        let accounts = self.get_multiple_accounts(&edition_accounts).await?;
+
+        for (token, account) in scan.tokens.iter_mut().zip(accounts) {
+            let account = match account {
+                Some(account) => account,
+                None => continue,
+            };
+
+            match decode_edition(&token.mint, &account.data) {
+                Ok(edition) => token.edition = edition,
+                Err(err) => {
+                    log::warn!("skipping edition of mint {}: {}", token.mint, err);
+                    scan.warnings.push(err);
+                }
+            }
+        }
+
+        let mut parents: Vec<Pubkey> = vec![];
+        for token in scan.tokens.iter() {
+            if let Some(parent) = token.edition.as_ref().and_then(|x| x.parent.as_ref()) {
+                let parent = parse_pubkey(parent)?;
+                if !parents.contains(&parent) {
+                    parents.push(parent);
+                }
+            }
+        }
+        let accounts = self.get_multiple_accounts(&parents).await?;
+
+        for (parent, account) in parents.iter().zip(accounts) {
+            let master = match account {
+                Some(account) => match decode_edition(parent, &account.data) {
+                    Ok(Some(master)) if master.kind == EditionKind::Master => master,
+                    _ => continue,
+                },
+                None => continue,
+            };
+
+            let parent = parent.to_string();
+            for edition in scan.tokens.iter_mut().filter_map(|x| x.edition.as_mut()) {
+                if edition.parent.as_ref() == Some(&parent) {
+                    edition.supply = master.supply;
+                    edition.max_supply = master.max_supply;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Metadata of each `(mint, amount)` holding, found through its mint so
+    /// that Token-2022 metadata pointers and embedded metadata are followed,
+    /// with the amount, supply, edition and, for mints without decimals, class
+    /// filled in. Holdings whose mint or
+    /// metadata account doesn't exist are `None`, those that fail to decode
+    /// are `None` with the error pushed to `warnings`.
+    async fn load_tokens(
+        &self,
+        holdings: &[(Pubkey, u64)],
+        warnings: &mut Vec<CrawlerError>,
+    ) -> Result<Vec<Option<TokenMetadata>>> {
+        let mints: Vec<Pubkey> = holdings.iter().map(|(mint, _)| *mint).collect();
+        let mint_accounts = self.get_multiple_accounts(&mints).await?;
+
+        let mut mint_infos = vec![];
+        for (index, (&(mint, amount), account)) in holdings.iter().zip(mint_accounts).enumerate() {
+            let account_data = match account {
+                Some(account) => account.data,
+                None => continue,
+            };
+
+            match decode_mint(&mint, &account_data) {
+                Ok(info) => mint_infos.push((index, mint, amount, info)),
+                Err(err) => {
+                    log::warn!("skipping mint {}: {}", mint, err);
+                    warnings.push(err);
+                }
+            }
+        }
+
+        let metadata_accounts: Vec<Pubkey> = mint_infos
+            .iter()
+            .filter_map(|(_, _, _, info)| match info.metadata {
+                MetadataLocation::Account(address) => Some(address),
+                MetadataLocation::Embedded(_) => None,
+            })
+            .collect();
+        let mut accounts = self
+            .get_multiple_accounts(&metadata_accounts)
+            .await?
+            .into_iter();
+
+        let mut resolved = vec![];
+        let mut scan = WalletNfts::default();
+        for (index, mint, amount, info) in mint_infos {
+            let token = match info.metadata {
+                MetadataLocation::Embedded(token) => Ok(*token),
+                MetadataLocation::Account(_) => match accounts.next().flatten() {
+                    Some(account) => decode_metadata_account(&mint, &account),
+                    None => continue,
+                },
+            };
+
+            match token {
+                Ok(token) => {
+                    resolved.push((index, info.decimals, info.mint_authority.is_none()));
+                    scan.tokens.push(TokenMetadata {
+                        amount,
+                        supply: info.supply,
+                        ..token
+                    });
+                }
+                Err(err) => {
+                    log::warn!("skipping mint {}: {}", mint, err);
+                    warnings.push(err);
+                }
+            }
+        }
+
+        self.resolve_editions(&mut scan).await?;
+        warnings.append(&mut scan.warnings);
+
+        let mut tokens = vec![None; holdings.len()];
+        for ((index, decimals, fixed_supply), mut token) in resolved.into_iter().zip(scan.tokens) {
+            if decimals == 0 {
+                token.class = classify(&token, fixed_supply);
+            }
+            tokens[index] = Some(token);
+        }
+        Ok(tokens)
+    }
+
+    /// Token program and decoded state of the mint account `mint`.
+    async fn get_mint_info(&self, mint: &Pubkey) -> Result<(Pubkey, MintInfo)> {
+        let account = self
+            .get_multiple_accounts(&[*mint])
+            .await?
+            .pop()
+            .flatten()
+            .ok_or_else(|| CrawlerError::AccountNotFound(mint.to_string()))?;
+        if account.owner != spl_token::id() && account.owner != spl_token_2022::id() {
+            return Err(CrawlerError::UnsupportedAccount(format!(
+                "{} is not a mint",
+                mint
+            )));
+        }
+
+        Ok((account.owner, decode_mint(mint, &account.data)?))
+    }
+
+    /// Token accounts owned by `owner` under the legacy token program and
+    /// under Token-2022, in that order, followed by its lamports. All three
+    /// are fetched in a single batched request.
+    async fn get_owner_accounts(
+        &self,
+        owner: &Pubkey,
+    ) -> Result<(Vec<RpcKeyedAccount>, Vec<RpcKeyedAccount>, u64)> {
+        let commitment = self.client.commitment();
+        let token_accounts_request = |program_id: Pubkey| -> BatchRequest {
+            let config = RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::JsonParsed),
+                commitment: Some(commitment),
+                ..Default::default()
+            };
+            let params = serde_json::json!([
+                owner.to_string(),
+                { "programId": program_id.to_string() },
+                config,
+            ]);
+            (RpcRequest::GetTokenAccountsByOwner, params)
+        };
+
+        let requests = vec![
+            token_accounts_request(spl_token::id()),
+            token_accounts_request(spl_token_2022::id()),
+            (
+                RpcRequest::GetBalance,
+                serde_json::json!([owner.to_string(), commitment]),
             ),
+        ];
+        let mut responses = self.client.sender().send_batch(requests).await?.into_iter();
+        let token_request = RpcRequest::GetTokenAccountsByOwner;
+
+        Ok((
+            batch_value(token_request, responses.next())?,
+            batch_value(token_request, responses.next())?,
+            batch_value(RpcRequest::GetBalance, responses.next())?,
+        ))
+    }
+
+    /// Fetches `keys` in chunks of `MAX_MULTIPLE_ACCOUNTS`, preserving order.
+    /// Missing accounts are returned as `None`.
+    async fn get_multiple_accounts(&self, keys: &[Pubkey]) -> Result<Vec<Option<Account>>> {
+        // The requests are created up front, an async closure borrowing each
+        // chunk would keep the returned future from being `Send`.
+        let requests: Vec<_> = keys
+            .chunks(MAX_MULTIPLE_ACCOUNTS)
+            .map(|chunk| {
+                self.client
+                    .get_multiple_accounts_with_commitment(chunk, CommitmentConfig::confirmed())
+            })
+            .collect();
+        let chunks = stream::iter(requests)
+            .buffered(MULTIPLE_ACCOUNTS_CONCURRENCY)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut accounts = Vec::with_capacity(keys.len());
+        for chunk in chunks {
+            accounts.extend(chunk?.value);
+        }
+
+        Ok(accounts)
+    }
+}
+
+/// Decodes the context wrapped `value` of one batch item.
+fn batch_value<T: DeserializeOwned>(
+    request: RpcRequest,
+    response: Option<client_error::Result<serde_json::Value>>,
+) -> Result<T> {
+    let json = match response {
+        Some(response) => response?,
+        None => {
+            return Err(CrawlerError::RpcResponse(Box::new(
+                ClientError::new_with_request(
+                    RpcError::RpcRequestError("missing batch response".to_string()).into(),
+                    request,
+                ),
+            )))
+        }
+    };
+
+    serde_json::from_value::<Response<T>>(json)
+        .map(|response| response.value)
+        .map_err(|err| {
+            CrawlerError::RpcResponse(Box::new(ClientError::new_with_request(err.into(), request)))
+        })
+}
+
+/// Whether a notification of a watched wallet can mean it gained or lost an
+/// NFT. Token accounts with decimals can't hold one.
+fn may_change_nfts(event: &PubsubEvent) -> bool {
+    match event {
+        PubsubEvent::Connected | PubsubEvent::Account { .. } => true,
+        PubsubEvent::Program { account, .. } => parse_token_account(&account.account)
+            .map(|token_account| token_account.token_amount.decimals == 0)
+            .unwrap_or(true),
+    }
+}
+
+/// Replaces `held` with `tokens` and returns what changed, losses first.
+fn diff_nfts(
+    held: &mut HashMap<String, TokenMetadata>,
+    tokens: Vec<TokenMetadata>,
+) -> Vec<WalletEvent> {
+    let mut current: HashMap<String, TokenMetadata> = tokens
+        .into_iter()
+        .map(|token| (token.mint.clone(), token))
+        .collect();
+
+    let mut lost: Vec<TokenMetadata> = held
+        .iter()
+        .filter(|(mint, _)| !current.contains_key(*mint))
+        .map(|(_, token)| token.clone())
+        .collect();
+    let mut gained: Vec<TokenMetadata> = current
+        .iter()
+        .filter(|(mint, _)| !held.contains_key(*mint))
+        .map(|(_, token)| token.clone())
+        .collect();
+    lost.sort_by(|a, b| a.mint.cmp(&b.mint));
+    gained.sort_by(|a, b| a.mint.cmp(&b.mint));
+
+    std::mem::swap(held, &mut current);
+    lost.into_iter()
+        .map(WalletEvent::Lost)
+        .chain(gained.into_iter().map(WalletEvent::Gained))
+        .collect()
+}
+
+fn parse_pubkey(addr: &str) -> Result<Pubkey> {
+    Pubkey::from_str(addr).map_err(|_| CrawlerError::InvalidAddress(addr.to_string()))
+}
+
+fn parse_token_account(account: &UiAccount) -> Result<UiTokenAccount> {
+    let program_owner = parse_pubkey(account.owner.as_str())?;
+    let program_name = PARSABLE_PROGRAM_IDS
+        .get(&program_owner)
+        .ok_or_else(|| CrawlerError::UnsupportedAccount(format!("owner {}", program_owner)))?;
+
+    let data = match &account.data {
+        UiAccountData::Json(data) => data,
+        _ => {
+            return Err(CrawlerError::UnsupportedAccount(
+                "token account data is not json parsed".into(),
+            ))
+        }
+    };
+
+    match program_name {
+        ParsableAccount::SplToken | ParsableAccount::SplToken2022 => {
+            match serde_json::from_value::<TokenAccountType>(data.parsed.clone()) {
+                Ok(TokenAccountType::Account(t)) => Ok(t),
+                Ok(_) => Err(CrawlerError::UnsupportedAccount(
+                    "expected a token account, found a mint or multisig".into(),
+                )),
+                Err(err) => Err(CrawlerError::UnsupportedAccount(err.to_string())),
+            }
+        }
+        other => Err(CrawlerError::UnsupportedAccount(format!("{:?}", other))),
+    }
+}
+
+/// Returns the mint and raw amount of a token account that could hold an
+/// NFT or SFT, i.e. a non-empty balance of a mint without decimals. Whether
+/// it is kept is decided by `classify` once the mint and edition are known.
+fn nft_candidate_for_account(account: &UiAccount) -> Result<Option<(Pubkey, u64)>> {
+    let token_account = parse_token_account(account)?;
+    if token_account.token_amount.decimals != 0 {
+        return Ok(None);
+    }
+
+    let (mint, amount) = token_holding(&token_account)?;
+    Ok((amount != 0).then_some((mint, amount)))
+}
+
+/// Mint and raw amount of a token account.
+fn token_holding(token_account: &UiTokenAccount) -> Result<(Pubkey, u64)> {
+    let amount = &token_account.token_amount.amount;
+    let amount = amount.parse::<u64>().map_err(|err| {
+        CrawlerError::UnsupportedAccount(format!("invalid token amount `{}`: {}", amount, err))
+    })?;
+    Ok((parse_pubkey(&token_account.mint)?, amount))
+}
+
+/// Decides what a token is from its amount, mint supply, edition and token
+/// standard. Only called for mints without decimals. `fixed_supply` is set
+/// when the mint authority is gone, which makes a supply of one a one of one
+/// even without a Metaplex edition, as with Token-2022 metadata.
+fn classify(token: &TokenMetadata, fixed_supply: bool) -> Option<TokenClass> {
+    if token.compressed {
+        return Some(TokenClass::Nft);
+    }
+    if token.amount == 0 || token.supply == 0 {
+        return None;
+    }
+
+    let programmable = matches!(
+        token.token_standard.as_deref(),
+        Some("ProgrammableNonFungible") | Some("ProgrammableNonFungibleEdition")
+    );
+    let fungible = matches!(
+        token.token_standard.as_deref(),
+        Some("Fungible") | Some("FungibleAsset")
+    );
+    let one_of_one = token.edition.is_some() || (fixed_supply && !fungible);
+    let unique = token.supply == 1 && token.amount == 1 && one_of_one;
+    match (unique, programmable) {
+        (true, true) => Some(TokenClass::ProgrammableNft),
+        (true, false) => Some(TokenClass::Nft),
+        (false, _) => Some(TokenClass::Sft),
+    }
+}
+
+/// Where the metadata of a mint lives.
+enum MetadataLocation {
+    /// A separate account, either a Metaplex PDA or the target of a
+    /// Token-2022 metadata pointer.
+    Account(Pubkey),
+    /// Stored in the mint itself through the Token-2022 token-metadata
+    /// extension.
+    Embedded(Box<TokenMetadata>),
+}
+
+/// What the crawler needs to know about a mint account.
+struct MintInfo {
+    supply: u64,
+    decimals: u8,
+    mint_authority: Option<Pubkey>,
+    freeze_authority: Option<Pubkey>,
+    metadata: MetadataLocation,
+}
+
+/// Decodes a legacy or Token-2022 mint. Token-2022 mints may point at their
+/// metadata through the metadata-pointer extension, everything else uses the
+/// Metaplex PDA.
+fn decode_mint(mint: &Pubkey, data: &[u8]) -> Result<MintInfo> {
+    let state = StateWithExtensions::<Mint>::unpack(data)
+        .map_err(|err| CrawlerError::metadata_decode(mint, err))?;
+
+    let pointer = state
+        .get_extension::<MetadataPointer>()
+        .ok()
+        .and_then(|pointer| Option::<Pubkey>::from(pointer.metadata_address));
+
+    let metadata = match pointer {
+        Some(address) if address == *mint => {
+            let extension = state
+                .get_variable_len_extension::<TokenMetadataExtension>()
+                .map_err(|err| CrawlerError::metadata_decode(mint, err))?;
+            let token = token_metadata_from_extension(mint, extension)?;
+            MetadataLocation::Embedded(Box::new(token))
+        }
+        Some(address) => MetadataLocation::Account(address),
+        None => MetadataLocation::Account(find_metadata_account(mint).0),
+    };
+
+    Ok(MintInfo {
+        supply: state.base.supply,
+        decimals: state.base.decimals,
+        mint_authority: state.base.mint_authority.into(),
+        freeze_authority: state.base.freeze_authority.into(),
+        metadata,
+    })
+}
+
+fn decode_metadata_account(mint: &Pubkey, account: &Account) -> Result<TokenMetadata> {
+    if account.owner == mpl_token_metadata::id() {
+        return decode_metadata(mint, &account.data);
+    }
+
+    let extension = TlvStateBorrowed::unpack(&account.data)
+        .and_then(|state| state.get_first_variable_len_value::<TokenMetadataExtension>())
+        .map_err(|err| CrawlerError::metadata_decode(mint, err))?;
+    token_metadata_from_extension(mint, extension)
+}
+
+fn token_metadata_from_extension(
+    mint: &Pubkey,
+    extension: TokenMetadataExtension,
+) -> Result<TokenMetadata> {
+    if extension.mint != *mint {
+        return Err(CrawlerError::metadata_decode(
+            mint,
+            format!("metadata belongs to mint {}", extension.mint),
+        ));
+    }
+
+    let update_authority = Option::<Pubkey>::from(extension.update_authority);
+
+    Ok(TokenMetadata {
+        is_mutable: update_authority.is_some(),
+        update_authority: update_authority.map(|x| x.to_string()).unwrap_or_default(),
+        mint: extension.mint.to_string(),
+        name: trim_null(&extension.name),
+        symbol: trim_null(&extension.symbol),
+        uri: encode_uri(mint, &extension.uri)?,
+        ..Default::default()
+    })
+}
+
+fn token_metadata_from_asset(asset: das::Asset) -> Result<TokenMetadata> {
+    let mint = parse_pubkey(&asset.id)?;
+    let update_authority = asset
+        .authorities
+        .iter()
+        .find(|x| x.scopes.iter().any(|scope| scope == "full"))
+        .or_else(|| asset.authorities.first())
+        .map(|x| x.address.clone())
+        .unwrap_or_default();
+
+    let (name, symbol, uri, token_standard) = match asset.content {
+        Some(content) => {
+            let (name, symbol, token_standard) = content
+                .metadata
+                .map(|x| (x.name, x.symbol, x.token_standard))
+                .unwrap_or_default();
+            (name, symbol, content.json_uri, token_standard)
+        }
+        None => Default::default(),
+    };
+
+    let creators = asset
+        .creators
+        .into_iter()
+        .map(|x| TokenCreator {
+            address: x.address,
+            verified: x.verified,
+            share: x.share,
+        })
+        .collect();
+
+    let collection = asset
+        .grouping
+        .into_iter()
+        .find(|x| x.group_key == "collection")
+        .map(|x| TokenCollection {
+            key: x.group_value,
+            verified: x.verified.unwrap_or(true),
+        });
+
+    let (seller_fee_basis_points, primary_sale_happened) = asset
+        .royalty
+        .map(|x| (x.basis_points, x.primary_sale_happened))
+        .unwrap_or_default();
+
+    Ok(TokenMetadata {
+        update_authority,
+        mint: asset.id,
+        name: trim_null(&name),
+        symbol: trim_null(&symbol),
+        uri: encode_uri(&mint, &uri)?,
+        compressed: true,
+        seller_fee_basis_points,
+        creators,
+        collection,
+        primary_sale_happened,
+        is_mutable: asset.mutable,
+        edition_nonce: asset.supply.and_then(|x| x.edition_nonce),
+        token_standard,
+        uses: None,
+        edition: None,
+        amount: 1,
+        supply: 1,
+        class: Some(TokenClass::Nft),
+    })
+}
+
+fn decode_metadata(mint: &Pubkey, data: &[u8]) -> Result<TokenMetadata> {
+    let mut sliced_data = data;
+    let meta = Metadata::deserialize(&mut sliced_data)
+        .map_err(|err| CrawlerError::metadata_decode(mint, err))?;
+
+    let creators = meta
+        .data
+        .creators
+        .unwrap_or_default()
+        .into_iter()
+        .map(|x| TokenCreator {
+            address: x.address.to_string(),
+            verified: x.verified,
+            share: x.share,
+        })
+        .collect();
+
+    Ok(TokenMetadata {
+        update_authority: meta.update_authority.to_string(),
+        mint: meta.mint.to_string(),
+        name: trim_null(&meta.data.name),
+        symbol: trim_null(&meta.data.symbol),
+        uri: encode_uri(mint, &meta.data.uri)?,
+        compressed: false,
+        seller_fee_basis_points: meta.data.seller_fee_basis_points,
+        creators,
+        collection: meta.collection.map(|x| TokenCollection {
+            key: x.key.to_string(),
+            verified: x.verified,
+        }),
+        primary_sale_happened: meta.primary_sale_happened,
+        is_mutable: meta.is_mutable,
+        edition_nonce: meta.edition_nonce,
+        token_standard: meta.token_standard.map(|x| format!("{:?}", x)),
+        uses: meta.uses.map(|x| TokenUses {
+            use_method: format!("{:?}", x.use_method),
+            remaining: x.remaining,
+            total: x.total,
+        }),
+        ..Default::default()
+    })
+}
+
+/// Decodes a master or print edition account. Returns `None` for accounts
+/// that are not editions.
+fn decode_edition<M: ToString>(mint: M, data: &[u8]) -> Result<Option<TokenEdition>> {
+    let mut sliced_data = data;
+    let key = match data.first() {
+        Some(key) => *key,
+        None => return Ok(None),
+    };
+
+    if key == Key::MasterEditionV1 as u8 || key == Key::MasterEditionV2 as u8 {
+        // V1 and V2 share the leading key, supply and max supply fields.
+        let master = MasterEditionV2::deserialize(&mut sliced_data)
+            .map_err(|err| CrawlerError::metadata_decode(mint, err))?;
+        Ok(Some(TokenEdition {
+            kind: EditionKind::Master,
+            number: None,
+            supply: Some(master.supply),
+            max_supply: master.max_supply,
+            parent: None,
+        }))
+    } else if key == Key::EditionV1 as u8 {
+        let edition = Edition::deserialize(&mut sliced_data)
+            .map_err(|err| CrawlerError::metadata_decode(mint, err))?;
+        Ok(Some(TokenEdition {
+            kind: EditionKind::Print,
+            number: Some(edition.edition),
+            supply: None,
+            max_supply: None,
+            parent: Some(edition.parent.to_string()),
+        }))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Wraps the uri the way `/load_metadata` expects it.
+fn encode_uri(mint: &Pubkey, uri: &str) -> Result<String> {
+    let uri = Some(trim_null(uri));
+    let uri = bincode::serialize(&uri).map_err(|err| CrawlerError::metadata_decode(mint, err))?;
+    Ok(base64::encode(uri))
+}
+
+/// Reverses `encode_uri`.
+fn decode_uri(encoded_uri: &str) -> Option<String> {
+    let uri = base64::decode(encoded_uri).ok()?;
+    bincode::deserialize::<Option<String>>(&uri).ok()?
+}
+
+fn trim_null(value: &str) -> String {
+    value.trim_end_matches('\0').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::cassette::ReplaySender;
+    use spl_token_2022::extension::{ExtensionType, StateWithExtensionsMut};
+
+    /// Cassette of a wallet holding one NFT, one of 500 SFTs, a fungible
+    /// token, a Token-2022 fungible token with embedded metadata and a
+    /// malformed token account. Re-recording it with
+    /// `examples/record_wallet.rs` changes the expectations below.
+    const WALLET_CASSETTE: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/wallet.jsonl");
+    const WALLET: &str = "4Qkev8aNZcqFNSRhQzwyLMFSsi94jHqE8WNVTJzTP99F";
+
+    fn replay_crawler() -> SolanaCrawler {
+        let sender = ReplaySender::open(WALLET_CASSETTE).unwrap();
+        SolanaCrawler::new_client(SolanaClient::new_sender(sender))
+    }
+
+    fn token_2022_mint(mint: &Pubkey, metadata_address: Option<Pubkey>) -> Vec<u8> {
+        let extension = TokenMetadataExtension {
+            mint: *mint,
+            name: "Token".into(),
+            symbol: "TKN".into(),
+            uri: "https://example.com/token.json".into(),
+            ..Default::default()
+        };
+        let len =
+            ExtensionType::try_calculate_account_len::<Mint>(&[ExtensionType::MetadataPointer])
+                .unwrap()
+                + extension.tlv_size_of().unwrap();
+
+        let mut data = vec![0; len];
+        let mut state = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+        let pointer = state.init_extension::<MetadataPointer>(true).unwrap();
+        pointer.metadata_address = metadata_address.try_into().unwrap();
+        state.base = Mint {
+            is_initialized: true,
+            supply: 1,
+            ..Default::default()
+        };
+        state.pack_base();
+        state.init_account_type().unwrap();
+        state
+            .init_variable_len_extension(&extension, false)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn token_2022_embedded_metadata() {
+        let mint = Pubkey::new_unique();
+        let data = token_2022_mint(&mint, Some(mint));
+
+        let info = decode_mint(&mint, &data).unwrap();
+        assert_eq!(info.supply, 1);
+        match info.metadata {
+            MetadataLocation::Embedded(token) => {
+                assert_eq!(token.mint, mint.to_string());
+                assert_eq!(token.name, "Token");
+                assert_eq!(token.symbol, "TKN");
+                assert_eq!(token.update_authority, "");
+            }
+            MetadataLocation::Account(_) => panic!("expected embedded metadata"),
+        }
+    }
+
+    #[test]
+    fn token_2022_metadata_account_locations() {
+        let mint = Pubkey::new_unique();
+        let external = Pubkey::new_unique();
+
+        let data = token_2022_mint(&mint, Some(external));
+        assert!(matches!(
+            decode_mint(&mint, &data).unwrap().metadata,
+            MetadataLocation::Account(address) if address == external
+        ));
+
+        let data = token_2022_mint(&mint, None);
+        assert!(matches!(
+            decode_mint(&mint, &data).unwrap().metadata,
+            MetadataLocation::Account(address) if address == find_metadata_account(&mint).0
+        ));
+    }
+
+    #[test]
+    fn compressed_asset_to_token_metadata() {
+        let mint = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let asset = serde_json::from_value::<das::Asset>(serde_json::json!({
+            "interface": "V1_NFT",
+            "id": mint.to_string(),
+            "content": {
+                "json_uri": "https://example.com/1.json",
+                "metadata": { "name": "Leaf #1", "symbol": "LEAF" }
+            },
+            "authorities": [{ "address": authority.to_string(), "scopes": ["full"] }],
+            "compression": { "compressed": true, "tree": "", "leaf_id": 1 },
+            "burnt": false
+        }))
+        .unwrap();
+        assert!(asset.is_compressed());
+
+        let token = token_metadata_from_asset(asset).unwrap();
+        assert_eq!(token.mint, mint.to_string());
+        assert_eq!(token.update_authority, authority.to_string());
+        assert_eq!(token.name, "Leaf #1");
+        assert_eq!(token.symbol, "LEAF");
+        assert!(token.compressed);
+    }
+
+    #[test]
+    fn decode_metaplex_metadata() {
+        use mpl_token_metadata::state::{Collection, Creator, Data, Key, TokenStandard};
+
+        let mint = Pubkey::new_unique();
+        let creator = Pubkey::new_unique();
+        let collection = Pubkey::new_unique();
+        let metadata = Metadata {
+            key: Key::MetadataV1,
+            update_authority: Pubkey::new_unique(),
+            mint,
+            data: Data {
+                name: "Token #1\0\0".into(),
+                symbol: "TKN\0".into(),
+                uri: "https://example.com/1.json\0".into(),
+                seller_fee_basis_points: 500,
+                creators: Some(vec![Creator {
+                    address: creator,
+                    verified: true,
+                    share: 100,
+                }]),
+            },
+            primary_sale_happened: true,
+            is_mutable: false,
+            edition_nonce: Some(254),
+            token_standard: Some(TokenStandard::NonFungible),
+            collection: Some(Collection {
+                verified: true,
+                key: collection,
+            }),
+            uses: None,
+            collection_details: None,
+            programmable_config: None,
+        };
+        let data = borsh::BorshSerialize::try_to_vec(&metadata).unwrap();
+
+        let token = decode_metadata(&mint, &data).unwrap();
+        assert_eq!(token.name, "Token #1");
+        assert_eq!(token.symbol, "TKN");
+        assert_eq!(token.seller_fee_basis_points, 500);
+        assert_eq!(token.creators.len(), 1);
+        assert_eq!(token.creators[0].address, creator.to_string());
+        assert!(token.creators[0].verified);
+        assert_eq!(
+            token.collection,
+            Some(TokenCollection {
+                key: collection.to_string(),
+                verified: true,
+            })
+        );
+        assert!(token.primary_sale_happened);
+        assert!(!token.is_mutable);
+        assert_eq!(token.edition_nonce, Some(254));
+        assert_eq!(token.token_standard.as_deref(), Some("NonFungible"));
+    }
+
+    #[test]
+    fn decode_master_and_print_editions() {
+        let mint = Pubkey::new_unique();
+        let master = MasterEditionV2 {
+            key: Key::MasterEditionV2,
+            supply: 37,
+            max_supply: Some(500),
+        };
+        let data = borsh::BorshSerialize::try_to_vec(&master).unwrap();
+        let edition = decode_edition(mint, &data).unwrap().unwrap();
+        assert_eq!(edition.kind, EditionKind::Master);
+        assert_eq!(edition.supply, Some(37));
+        assert_eq!(edition.max_supply, Some(500));
+
+        let parent = Pubkey::new_unique();
+        let print = Edition {
+            key: Key::EditionV1,
+            parent,
+            edition: 37,
+        };
+        let data = borsh::BorshSerialize::try_to_vec(&print).unwrap();
+        let edition = decode_edition(mint, &data).unwrap().unwrap();
+        assert_eq!(edition.kind, EditionKind::Print);
+        assert_eq!(edition.number, Some(37));
+        assert_eq!(edition.parent, Some(parent.to_string()));
+
+        assert_eq!(
+            decode_edition(mint, &[Key::MetadataV1 as u8]).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn encoded_uri_round_trip() {
+        let mint = Pubkey::new_unique();
+        let encoded = encode_uri(&mint, "https://example.com/1.json\0\0").unwrap();
+        assert_eq!(
+            decode_uri(&encoded).as_deref(),
+            Some("https://example.com/1.json")
+        );
+        assert_eq!(decode_uri("not base64!"), None);
+    }
+
+    fn parsed_token_account(mint: &Pubkey, amount: &str, decimals: u8) -> UiAccount {
+        let ui_amount = amount.parse::<f64>().unwrap() / 10f64.powi(decimals as i32);
+        serde_json::from_value(serde_json::json!({
+            "lamports": 2039280,
+            "data": {
+                "program": "spl-token",
+                "parsed": {
+                    "type": "account",
+                    "info": {
+                        "mint": mint.to_string(),
+                        "owner": Pubkey::new_unique().to_string(),
+                        "tokenAmount": {
+                            "amount": amount,
+                            "decimals": decimals,
+                            "uiAmount": ui_amount,
+                            "uiAmountString": ui_amount.to_string(),
+                        },
+                        "state": "frozen",
+                        "isNative": false,
+                    },
+                },
+                "space": 165,
+            },
+            "owner": spl_token::id().to_string(),
+            "executable": false,
+            "rentEpoch": 0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_token_accounts() {
+        let mint = Pubkey::new_unique();
+
+        let account = parsed_token_account(&mint, "1", 0);
+        assert_eq!(
+            nft_candidate_for_account(&account).unwrap(),
+            Some((mint, 1))
+        );
+
+        let account = parsed_token_account(&mint, "25", 0);
+        assert_eq!(
+            nft_candidate_for_account(&account).unwrap(),
+            Some((mint, 25))
+        );
+
+        let account = parsed_token_account(&mint, "0", 0);
+        assert_eq!(nft_candidate_for_account(&account).unwrap(), None);
+
+        // 1.0 with decimals used to round-trip to an amount of one.
+        let account = parsed_token_account(&mint, "1000000", 6);
+        let token_account = parse_token_account(&account).unwrap();
+        assert_eq!(token_account.token_amount.amount, "1000000");
+        assert_eq!(token_account.state, UiAccountState::Frozen);
+        assert_eq!(nft_candidate_for_account(&account).unwrap(), None);
+    }
+
+    #[test]
+    fn classify_tokens() {
+        let master = Some(TokenEdition {
+            kind: EditionKind::Master,
+            number: None,
+            supply: Some(0),
+            max_supply: Some(0),
+            parent: None,
         });
 
-        result
+        let nft = TokenMetadata {
+            amount: 1,
+            supply: 1,
+            edition: master.clone(),
+            token_standard: Some("NonFungible".into()),
+            ..Default::default()
+        };
+        assert_eq!(classify(&nft, false), Some(TokenClass::Nft));
+
+        let pnft = TokenMetadata {
+            token_standard: Some("ProgrammableNonFungible".into()),
+            ..nft.clone()
+        };
+        assert_eq!(classify(&pnft, false), Some(TokenClass::ProgrammableNft));
+
+        let no_edition = TokenMetadata {
+            edition: None,
+            ..nft.clone()
+        };
+        assert_eq!(classify(&no_edition, false), Some(TokenClass::Sft));
+
+        // Token-2022 NFT with embedded metadata and no Metaplex edition.
+        let mint = Pubkey::new_unique();
+        let info = decode_mint(&mint, &token_2022_mint(&mint, Some(mint))).unwrap();
+        let token_2022 = match info.metadata {
+            MetadataLocation::Embedded(token) => TokenMetadata {
+                amount: 1,
+                supply: info.supply,
+                ..*token
+            },
+            MetadataLocation::Account(_) => panic!("expected embedded metadata"),
+        };
+        assert_eq!(
+            classify(&token_2022, info.mint_authority.is_none()),
+            Some(TokenClass::Nft)
+        );
+        let fungible_asset = TokenMetadata {
+            token_standard: Some("FungibleAsset".into()),
+            ..no_edition.clone()
+        };
+        assert_eq!(classify(&fungible_asset, true), Some(TokenClass::Sft));
+
+        let one_of_many = TokenMetadata {
+            supply: 500,
+            ..nft.clone()
+        };
+        assert_eq!(classify(&one_of_many, false), Some(TokenClass::Sft));
+
+        let policy = ClassificationPolicy::strict();
+        assert!(policy.allows(TokenClass::Nft));
+        assert!(!policy.allows(TokenClass::ProgrammableNft));
+        assert!(!policy.allows(TokenClass::Sft));
+        assert!(ClassificationPolicy::all().allows(TokenClass::Sft));
+    }
+
+    #[tokio::test]
+    async fn replay_wallet_nfts() {
+        let scan = replay_crawler().get_nfts_for_owner(WALLET).await.unwrap();
+        assert!(scan.warnings.is_empty());
+        assert_eq!(scan.tokens.len(), 1);
+
+        let token = &scan.tokens[0];
+        assert_eq!(token.mint, "7TENEjB8Aw1jC2BpNHAHFe7HRCBx3ZiKTXbDqjmkrvSb");
+        assert_eq!(token.name, "Replay #1");
+        assert_eq!(token.symbol, "RPLY");
+        assert_eq!(
+            decode_uri(&token.uri).as_deref(),
+            Some("https://arweave.net/rply.json")
+        );
+        assert_eq!(token.class, Some(TokenClass::Nft));
+        assert_eq!(token.edition.as_ref().unwrap().kind, EditionKind::Master);
+        assert_eq!(
+            token.collection.as_ref().unwrap().key,
+            "SMBH3wF6baUj6JWtzYvqcKuj2XCKWDqQxzspY12xPND"
+        );
+    }
+
+    #[tokio::test]
+    async fn replay_wallet_in_one_scan() {
+        let crawler = replay_crawler();
+        let wallet = crawler.get_wallet(WALLET).await.unwrap();
+        let requests = crawler.client.sender().get_transport_stats().request_count;
+
+        let crawler = replay_crawler();
+        let nfts = crawler.get_nfts_for_owner(WALLET).await.unwrap();
+        let portfolio = crawler.get_token_balances_for_owner(WALLET).await.unwrap();
+        assert_eq!(wallet.nfts, nfts.tokens);
+        assert_eq!(wallet.portfolio.tokens.len(), portfolio.tokens.len());
+        assert_eq!(wallet.portfolio.warnings.len(), 1);
+
+        // The owner's accounts and their mints are fetched once, not once
+        // per view.
+        let separately = crawler.client.sender().get_transport_stats().request_count;
+        assert!(requests < separately, "{} < {}", requests, separately);
+    }
+
+    #[tokio::test]
+    async fn replay_wallet_portfolio() {
+        let portfolio = replay_crawler()
+            .get_token_balances_for_owner(WALLET)
+            .await
+            .unwrap();
+        assert_eq!(portfolio.lamports, 1_500_000_000);
+        assert_eq!(portfolio.sol(), 1.5);
+        // The NFT is left to the NFT listing.
+        assert_eq!(portfolio.tokens.len(), 3);
+
+        let balance = &portfolio.tokens[0];
+        assert_eq!(balance.amount, "2500000");
+        assert_eq!(balance.decimals, 6);
+        assert_eq!(balance.metadata.as_ref().unwrap().symbol, "USDC");
+
+        // One of 500, hidden from the NFT listing by the default policy.
+        let balance = &portfolio.tokens[1];
+        assert_eq!(balance.amount, "1");
+        let metadata = balance.metadata.as_ref().unwrap();
+        assert_eq!(metadata.class, Some(TokenClass::Sft));
+        assert_eq!(metadata.supply, 500);
+
+        // Token-2022 metadata embedded in the mint.
+        let balance = &portfolio.tokens[2];
+        assert_eq!(balance.amount, "1250000");
+        assert_eq!(balance.metadata.as_ref().unwrap().symbol, "PTS");
+
+        // A token account with a malformed mint doesn't fail the portfolio.
+        assert!(matches!(
+            portfolio.warnings.as_slice(),
+            [CrawlerError::InvalidAddress(mint)] if mint == "not-a-mint"
+        ));
+    }
+
+    #[tokio::test]
+    async fn replay_mint_lookups() {
+        let crawler = replay_crawler();
+        let nft = "7TENEjB8Aw1jC2BpNHAHFe7HRCBx3ZiKTXbDqjmkrvSb";
+
+        let mint = crawler.get_mint(nft).await.unwrap();
+        assert_eq!(mint.program, spl_token::id().to_string());
+        assert_eq!((mint.supply, mint.decimals), (1, 0));
+        assert_eq!(mint.mint_authority, None);
+        assert_eq!(
+            mint.metadata_address,
+            find_metadata_account(&parse_pubkey(nft).unwrap())
+                .0
+                .to_string()
+        );
+
+        let token = crawler.get_token_metadata(nft).await.unwrap();
+        assert_eq!(token.name, "Replay #1");
+        assert_eq!(token.supply, 1);
+        assert_eq!(token.class, Some(TokenClass::Nft));
+        assert_eq!(token.edition.unwrap().kind, EditionKind::Master);
+
+        assert!(matches!(
+            crawler.get_mint("not-a-mint").await,
+            Err(CrawlerError::InvalidAddress(_))
+        ));
+    }
+
+    #[test]
+    fn queries_nft_listings() {
+        let token =
+            |mint: &str, name: &str, symbol: &str, collection: Option<&str>| TokenMetadata {
+                mint: mint.into(),
+                name: name.into(),
+                symbol: symbol.into(),
+                update_authority: "authority".into(),
+                creators: vec![TokenCreator {
+                    address: format!("creator-{}", symbol),
+                    verified: true,
+                    share: 100,
+                }],
+                collection: collection.map(|key| TokenCollection {
+                    key: key.into(),
+                    verified: true,
+                }),
+                ..Default::default()
+            };
+        let tokens = vec![
+            token("m1", "banana", "FRT", Some("c2")),
+            token("m2", "Apple", "FRT", None),
+            token("m3", "cherry", "BRY", Some("c1")),
+            token("m4", "apple", "BRY", Some("c2")),
+        ];
+        let mints = |page: &NftPage| {
+            page.items
+                .iter()
+                .map(|x| x.mint.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let page = NftQuery::default().apply(tokens.clone());
+        assert_eq!(mints(&page), ["m1", "m2", "m3", "m4"]);
+        assert_eq!((page.total, page.next_offset), (4, None));
+
+        let by_name = NftQuery {
+            sort: Some(NftSort::Name),
+            limit: Some(3),
+            ..Default::default()
+        };
+        let page = by_name.apply(tokens.clone());
+        assert_eq!(mints(&page), ["m2", "m4", "m1"]);
+        assert_eq!(page.next_offset, Some(3));
+        let page = NftQuery {
+            offset: 3,
+            ..by_name.clone()
+        }
+        .apply(tokens.clone());
+        assert_eq!(mints(&page), ["m3"]);
+        assert_eq!(page.next_offset, None);
+
+        let page = NftQuery {
+            sort: Some(NftSort::Collection),
+            order: SortOrder::Desc,
+            ..Default::default()
+        }
+        .apply(tokens.clone());
+        assert_eq!(mints(&page), ["m2", "m1", "m4", "m3"]);
+
+        let page = NftQuery {
+            symbol: Some("frt".into()),
+            collection: Some("c2".into()),
+            creator: Some(String::new()),
+            ..Default::default()
+        }
+        .apply(tokens.clone());
+        assert_eq!(mints(&page), ["m1"]);
+        let page = NftQuery {
+            creator: Some("creator-BRY".into()),
+            update_authority: Some("authority".into()),
+            offset: 10,
+            ..Default::default()
+        }
+        .apply(tokens);
+        assert_eq!((page.total, page.offset, page.items.len()), (2, 2, 0));
+
+        let query: NftQuery = serde_urlencoded::from_str("sort=&order=desc").unwrap();
+        assert_eq!((query.sort, query.order), (None, SortOrder::Desc));
+        let query: NftQuery = serde_urlencoded::from_str("sort=mint").unwrap();
+        assert_eq!(query.sort, Some(NftSort::Mint));
+        assert!(serde_urlencoded::from_str::<NftQuery>("sort=size").is_err());
+
+        // Groups are paged like tokens, after the whole listing is grouped.
+        let page = NftQuery {
+            offset: 1,
+            limit: Some(1),
+            ..Default::default()
+        }
+        .page(vec!["c1", "c2", "authority"]);
+        assert_eq!(page.items, ["c2"]);
+        assert_eq!((page.total, page.next_offset), (3, Some(2)));
+    }
+
+    #[test]
+    fn diffs_wallet_nfts() {
+        let token = |mint: &str| TokenMetadata {
+            mint: mint.into(),
+            amount: 1,
+            ..Default::default()
+        };
+        let mut held: HashMap<String, TokenMetadata> =
+            [("a".to_string(), token("a")), ("b".to_string(), token("b"))].into();
+
+        let events = diff_nfts(&mut held, vec![token("b"), token("d"), token("c")]);
+        assert_eq!(
+            events,
+            vec![
+                WalletEvent::Lost(token("a")),
+                WalletEvent::Gained(token("c")),
+                WalletEvent::Gained(token("d")),
+            ]
+        );
+        assert!(diff_nfts(&mut held, vec![token("c"), token("b"), token("d")]).is_empty());
+
+        let json = serde_json::to_value(WalletEvent::Gained(token("c"))).unwrap();
+        assert_eq!(json["event"], "gained");
+        assert_eq!(json["mint"], "c");
     }
 }
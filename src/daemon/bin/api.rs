@@ -0,0 +1,389 @@
+use actix_web::{
+    get,
+    http::StatusCode,
+    web::{self, Json},
+    HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use solana_e::{
+    crawler::{MintAccount, NftQuery, SolanaCrawler, TokenBalance, TokenMetadata},
+    error::CrawlerError,
+};
+use std::fmt;
+use utoipa::ToSchema;
+
+use crate::{config::Config, load_wallet, page_query};
+
+/// Prefix of every api route, the handlers' paths are relative to it.
+pub const SCOPE: &str = "/api/v1";
+
+/// Expands `$then!(handler, ...)` with every handler served under `SCOPE`.
+/// The route table and the OpenAPI document are both built from this list,
+/// so a route cannot be served without being documented or the reverse.
+macro_rules! handlers {
+    ($then:ident) => {
+        $then! { wallet_nfts, wallet_tokens, mint_account, mint_metadata }
+    };
+}
+pub(crate) use handlers;
+
+/// Registers the `/api/v1` routes.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    macro_rules! scope {
+        ($($handler:ident),*) => {
+            web::scope(SCOPE)$(.service($handler))*
+        };
+    }
+
+    cfg.service(
+        handlers!(scope)
+            .app_data(web::QueryConfig::default().error_handler(|err, _| {
+                ApiError::new(StatusCode::BAD_REQUEST, "invalid_query", err).into()
+            }))
+            .default_service(web::to(not_found)),
+    );
+}
+
+/// Body of every error returned by the api.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ErrorBody {
+    pub error: ErrorDetail,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ErrorDetail {
+    /// Stable, machine readable reason such as `invalid_address`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new<M: ToString>(status: StatusCode, code: &'static str, message: M) -> Self {
+        Self {
+            status,
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(ErrorBody {
+            error: ErrorDetail {
+                code: self.code,
+                message: self.message.clone(),
+            },
+        })
+    }
+}
+
+impl From<CrawlerError> for ApiError {
+    fn from(err: CrawlerError) -> Self {
+        let (status, code) = match &err {
+            CrawlerError::InvalidAddress(_) => (StatusCode::BAD_REQUEST, "invalid_address"),
+            CrawlerError::AccountNotFound(_) => (StatusCode::NOT_FOUND, "account_not_found"),
+            CrawlerError::UnsupportedAccount(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "unsupported_account")
+            }
+            CrawlerError::MetadataDecode { .. } => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "metadata_decode")
+            }
+            CrawlerError::RpcTransport(_) | CrawlerError::RpcResponse(_) => {
+                (StatusCode::BAD_GATEWAY, "rpc_error")
+            }
+            CrawlerError::InvalidConfig(_) => (StatusCode::INTERNAL_SERVER_ERROR, "internal"),
+        };
+        if status.is_server_error() {
+            log::error!("api: {}", err);
+        }
+        Self::new(status, code, err)
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct WalletNftsResponse {
+    pub address: String,
+    /// Tokens on this page.
+    pub count: usize,
+    /// Tokens matching the filters, across all pages.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    /// Offset of the next page, if any.
+    pub next_offset: Option<usize>,
+    pub tokens: Vec<TokenMetadata>,
+    /// Tokens that could not be decoded, or lookups that failed without
+    /// failing the request.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct WalletTokensResponse {
+    pub address: String,
+    pub lamports: u64,
+    pub sol: f64,
+    pub tokens: Vec<TokenBalance>,
+    pub warnings: Vec<String>,
+}
+
+/// NFTs held by a wallet, compressed ones included unless disabled, one
+/// page at a time.
+#[utoipa::path(
+    context_path = SCOPE,
+    params(("address" = String, Path, description = "Wallet address."), NftQuery),
+    responses(
+        (status = 200, body = WalletNftsResponse),
+        (status = 400, description = "Invalid address or query.", body = ErrorBody),
+        (status = 502, description = "The RPC node failed.", body = ErrorBody),
+    )
+)]
+#[get("/wallets/{address}/nfts")]
+pub(crate) async fn wallet_nfts(
+    crawler: web::Data<SolanaCrawler>,
+    config: web::Data<Config>,
+    address: web::Path<String>,
+    query: web::Query<NftQuery>,
+) -> ApiResult<WalletNftsResponse> {
+    let scan = load_wallet(&crawler, &config, &address).await?;
+    let query = page_query(query.into_inner());
+    let page = query.apply(scan.tokens);
+
+    Ok(Json(WalletNftsResponse {
+        address: address.into_inner(),
+        count: page.items.len(),
+        total: page.total,
+        offset: page.offset,
+        limit: query.limit.unwrap_or_default(),
+        next_offset: page.next_offset,
+        tokens: page.items.into_iter().map(with_metadata_uri).collect(),
+        warnings: scan.warnings.iter().map(|x| x.to_string()).collect(),
+    }))
+}
+
+/// SOL and fungible token balances of a wallet.
+#[utoipa::path(
+    context_path = SCOPE,
+    params(("address" = String, Path, description = "Wallet address.")),
+    responses(
+        (status = 200, body = WalletTokensResponse),
+        (status = 400, description = "Invalid address.", body = ErrorBody),
+        (status = 502, description = "The RPC node failed.", body = ErrorBody),
+    )
+)]
+#[get("/wallets/{address}/tokens")]
+pub(crate) async fn wallet_tokens(
+    crawler: web::Data<SolanaCrawler>,
+    address: web::Path<String>,
+) -> ApiResult<WalletTokensResponse> {
+    let portfolio = crawler.get_token_balances_for_owner(&address).await?;
+
+    Ok(Json(WalletTokensResponse {
+        address: address.into_inner(),
+        lamports: portfolio.lamports,
+        sol: portfolio.sol(),
+        tokens: portfolio
+            .tokens
+            .into_iter()
+            .map(|balance| TokenBalance {
+                metadata: balance.metadata.map(with_metadata_uri),
+                ..balance
+            })
+            .collect(),
+        warnings: portfolio.warnings.iter().map(|x| x.to_string()).collect(),
+    }))
+}
+
+/// Supply, decimals and authorities of a mint.
+#[utoipa::path(
+    context_path = SCOPE,
+    params(("mint" = String, Path, description = "Mint address.")),
+    responses(
+        (status = 200, body = MintAccount),
+        (status = 400, description = "Invalid address.", body = ErrorBody),
+        (status = 404, description = "No such account.", body = ErrorBody),
+        (status = 422, description = "The account is not a mint or its metadata can't be decoded.", body = ErrorBody),
+        (status = 502, description = "The RPC node failed.", body = ErrorBody),
+    )
+)]
+#[get("/mints/{mint}")]
+pub(crate) async fn mint_account(
+    crawler: web::Data<SolanaCrawler>,
+    mint: web::Path<String>,
+) -> ApiResult<MintAccount> {
+    Ok(Json(crawler.get_mint(&mint).await?))
+}
+
+/// Metaplex or Token-2022 metadata of a mint.
+#[utoipa::path(
+    context_path = SCOPE,
+    params(("mint" = String, Path, description = "Mint address.")),
+    responses(
+        (status = 200, body = TokenMetadata),
+        (status = 400, description = "Invalid address.", body = ErrorBody),
+        (status = 404, description = "No such account.", body = ErrorBody),
+        (status = 422, description = "The account is not a mint or its metadata can't be decoded.", body = ErrorBody),
+        (status = 502, description = "The RPC node failed.", body = ErrorBody),
+    )
+)]
+#[get("/mints/{mint}/metadata")]
+pub(crate) async fn mint_metadata(
+    crawler: web::Data<SolanaCrawler>,
+    mint: web::Path<String>,
+) -> ApiResult<TokenMetadata> {
+    let token = crawler.get_token_metadata(&mint).await?;
+    Ok(Json(with_metadata_uri(token)))
+}
+
+async fn not_found() -> Result<HttpResponse, ApiError> {
+    Err(ApiError::new(
+        StatusCode::NOT_FOUND,
+        "not_found",
+        "no such api route",
+    ))
+}
+
+/// Api consumers get the plain off-chain uri rather than the form the html
+/// pages pass to `/load_metadata`.
+fn with_metadata_uri(token: TokenMetadata) -> TokenMetadata {
+    match token.metadata_uri() {
+        Some(uri) => TokenMetadata { uri, ..token },
+        None => token,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use solana_e::rpc::{cassette::ReplaySender, client::SolanaClient};
+
+    const WALLET: &str = "4Qkev8aNZcqFNSRhQzwyLMFSsi94jHqE8WNVTJzTP99F";
+    const NFT: &str = "7TENEjB8Aw1jC2BpNHAHFe7HRCBx3ZiKTXbDqjmkrvSb";
+
+    pub(crate) fn replay_app_data() -> (web::Data<SolanaCrawler>, web::Data<Config>) {
+        let cassette = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/wallet.jsonl");
+        let sender = ReplaySender::open(cassette).unwrap();
+        let crawler = SolanaCrawler::new_client(SolanaClient::new_sender(sender));
+
+        let mut config = Config::default();
+        config.features.compressed_nfts = false;
+        (web::Data::new(crawler), web::Data::new(config))
+    }
+
+    #[actix_web::test]
+    async fn serves_wallets_and_mints() {
+        let (crawler, config) = replay_app_data();
+        let app = test::init_service(
+            App::new()
+                .app_data(crawler)
+                .app_data(config)
+                .configure(configure),
+        )
+        .await;
+
+        let uri = format!("/api/v1/wallets/{}/nfts", WALLET);
+        let nfts: serde_json::Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request())
+                .await;
+        assert_eq!(nfts["count"], 1);
+        assert_eq!(nfts["total"], 1);
+        assert_eq!(nfts["next_offset"], serde_json::Value::Null);
+        assert_eq!(nfts["tokens"][0]["mint"], NFT);
+        assert_eq!(nfts["tokens"][0]["uri"], "https://arweave.net/rply.json");
+
+        let uri = format!(
+            "/api/v1/wallets/{}/nfts?symbol=rply&sort=name&order=desc&limit=10",
+            WALLET
+        );
+        let filtered: serde_json::Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request())
+                .await;
+        assert_eq!(filtered["count"], 1);
+        assert_eq!(filtered["limit"], 10);
+        let uri = format!("/api/v1/wallets/{}/nfts?creator=nobody", WALLET);
+        let none: serde_json::Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request())
+                .await;
+        assert_eq!(none["total"], 0);
+
+        let uri = format!("/api/v1/wallets/{}/tokens", WALLET);
+        let tokens: serde_json::Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request())
+                .await;
+        assert_eq!(tokens["lamports"], 1_500_000_000u64);
+        assert_eq!(tokens["tokens"][0]["metadata"]["symbol"], "USDC");
+
+        let uri = format!("/api/v1/mints/{}", NFT);
+        let mint: serde_json::Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request())
+                .await;
+        assert_eq!(mint["supply"], 1);
+        assert_eq!(mint["decimals"], 0);
+
+        let uri = format!("/api/v1/mints/{}/metadata", NFT);
+        let metadata: serde_json::Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request())
+                .await;
+        assert_eq!(metadata["name"], "Replay #1");
+        assert_eq!(metadata["class"], "nft");
+    }
+
+    #[actix_web::test]
+    async fn reports_errors_as_json() {
+        let (crawler, config) = replay_app_data();
+        let app = test::init_service(
+            App::new()
+                .app_data(crawler)
+                .app_data(config)
+                .configure(configure),
+        )
+        .await;
+
+        for (uri, status, code) in [
+            (
+                "/api/v1/wallets/not-an-address/nfts",
+                StatusCode::BAD_REQUEST,
+                "invalid_address",
+            ),
+            // Unknown to the cassette, which answers like a failing node.
+            (
+                "/api/v1/mints/11111111111111111111111111111111",
+                StatusCode::BAD_GATEWAY,
+                "rpc_error",
+            ),
+            ("/api/v1/nope", StatusCode::NOT_FOUND, "not_found"),
+            (
+                "/api/v1/wallets/not-an-address/nfts?sort=price",
+                StatusCode::BAD_REQUEST,
+                "invalid_query",
+            ),
+        ] {
+            let response =
+                test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.status(), status, "{}", uri);
+            let body: serde_json::Value = test::read_body_json(response).await;
+            assert_eq!(body["error"]["code"], code, "{}", uri);
+            assert!(body["error"]["message"].is_string());
+        }
+    }
+}
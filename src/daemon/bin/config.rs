@@ -0,0 +1,625 @@
+use clap::{Arg, ArgMatches, Command};
+use serde::Deserialize;
+use solana_e::{
+    crawler::SolanaCrawler,
+    rpc::{
+        cache::{self, CachingSender},
+        circuit_breaker::CircuitBreakerConfig,
+        client::SolanaClient,
+        custom_http_sender::{CustomHttpSender, CustomHttpSenderBuilder, HttpSenderConfig},
+        pool::PooledHttpSender,
+    },
+};
+use std::{
+    env, fs, io,
+    net::ToSocketAddrs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use thiserror::Error;
+
+/// Read when neither `--config` nor `SOLANA_E_CONFIG` is given, if present.
+const DEFAULT_CONFIG_FILE: &str = "solana-e.toml";
+
+/// Public endpoint used when none is configured. Rate limited, operators
+/// are expected to configure their own.
+const DEFAULT_RPC_URL: &str = "https://api.mainnet-beta.solana.com";
+
+/// Settings that can be overridden from the environment, as
+/// `SOLANA_E_<SETTING>` with dots replaced by underscores, or from the
+/// command line with `--set <setting>=<value>`.
+pub const SETTINGS: &[&str] = &[
+    "server.listen",
+    "server.workers",
+    "server.templates",
+    "server.log",
+    "server.shutdown_timeout_secs",
+    "rpc.urls",
+    "rpc.timeout_secs",
+    "rpc.health_check_interval_secs",
+    "cache.enabled",
+    "cache.account_ttl_secs",
+    "cache.token_accounts_ttl_secs",
+    "cache.max_entries",
+    "features.compressed_nfts",
+    "features.metadata_proxy",
+];
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("failed to read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+
+    #[error("failed to parse {}: {source}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error("unknown setting {0}")]
+    UnknownSetting(String),
+
+    #[error("invalid value {value:?} for {setting}: {reason}")]
+    InvalidValue {
+        setting: String,
+        value: String,
+        reason: String,
+    },
+
+    #[error("invalid configuration: {0}")]
+    Invalid(String),
+}
+
+pub type Result<T> = std::result::Result<T, ConfigError>;
+
+/// Daemon configuration. Loaded from a TOML file, then overridden by the
+/// environment and finally by command line flags.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub rpc: RpcConfig,
+    pub cache: CacheConfig,
+    pub features: FeatureToggles,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: String,
+    /// Defaults to the number of physical cores.
+    pub workers: Option<usize>,
+    /// Glob of the Tera templates.
+    pub templates: String,
+    /// Log filter used when `RUST_LOG` is not set.
+    pub log: String,
+    /// Time given to in-flight requests on shutdown.
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            listen: "0.0.0.0:8081".into(),
+            workers: None,
+            templates: concat!(env!("CARGO_MANIFEST_DIR"), "/templates/**/*").into(),
+            log: "solana_e=trace".into(),
+            shutdown_timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    /// Endpoints in order of preference.
+    pub endpoints: Vec<HttpSenderConfig>,
+    /// Request timeout of endpoints that don't set their own.
+    pub timeout_secs: u64,
+    /// Interval of the endpoint health checks, 0 to disable them.
+    pub health_check_interval_secs: u64,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: vec![HttpSenderConfig {
+                url: DEFAULT_RPC_URL.to_string(),
+                ..Default::default()
+            }],
+            timeout_secs: 30,
+            health_check_interval_secs: 30,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// Whether account lookups are cached, see `CachingSender`.
+    pub enabled: bool,
+    pub account_ttl_secs: u64,
+    pub token_accounts_ttl_secs: u64,
+    pub max_entries: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        let defaults = cache::CacheConfig::default();
+        Self {
+            enabled: true,
+            account_ttl_secs: defaults.account_ttl.as_secs(),
+            token_accounts_ttl_secs: defaults.token_accounts_ttl.as_secs(),
+            max_entries: defaults.max_entries,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn sender_config(&self) -> cache::CacheConfig {
+        cache::CacheConfig {
+            account_ttl: Duration::from_secs(self.account_ttl_secs),
+            token_accounts_ttl: Duration::from_secs(self.token_accounts_ttl_secs),
+            max_entries: self.max_entries,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeatureToggles {
+    /// Look up compressed NFTs through the DAS api.
+    pub compressed_nfts: bool,
+    /// Serve `/load_metadata`, which proxies off-chain metadata.
+    pub metadata_proxy: bool,
+}
+
+impl Default for FeatureToggles {
+    fn default() -> Self {
+        Self {
+            compressed_nfts: true,
+            metadata_proxy: true,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration for this process from its file, environment
+    /// and command line, and validates it.
+    pub fn load() -> Result<Self> {
+        let matches = command().get_matches();
+        let path = matches
+            .value_of("config")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("SOLANA_E_CONFIG").map(PathBuf::from));
+
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Self::default(),
+        };
+        config.apply_env(|name| env::var(name).ok())?;
+        config.apply_args(&matches)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Applies the `SOLANA_E_*` variables returned by `var`.
+    pub fn apply_env<F: Fn(&str) -> Option<String>>(&mut self, var: F) -> Result<()> {
+        for setting in SETTINGS {
+            if let Some(value) = var(&env_var(setting)) {
+                self.set(setting, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_args(&mut self, matches: &ArgMatches) -> Result<()> {
+        for assignment in matches.values_of("set").into_iter().flatten() {
+            let (setting, value) = assignment.split_once('=').ok_or_else(|| {
+                ConfigError::Invalid(format!(
+                    "expected --set <setting>=<value>, got {}",
+                    assignment
+                ))
+            })?;
+            self.set(setting, value)?;
+        }
+
+        let flags = [
+            ("listen", "server.listen"),
+            ("workers", "server.workers"),
+            ("templates", "server.templates"),
+            ("log", "server.log"),
+            ("rpc-timeout-secs", "rpc.timeout_secs"),
+            ("cache-max-entries", "cache.max_entries"),
+        ];
+        for (flag, setting) in flags {
+            if let Some(value) = matches.value_of(flag) {
+                self.set(setting, value)?;
+            }
+        }
+        if let Some(urls) = matches.values_of("rpc-url") {
+            self.set("rpc.urls", &urls.collect::<Vec<_>>().join(","))?;
+        }
+        if matches.is_present("no-cache") {
+            self.cache.enabled = false;
+        }
+        if matches.is_present("no-compressed-nfts") {
+            self.features.compressed_nfts = false;
+        }
+
+        Ok(())
+    }
+
+    /// Overrides one of `SETTINGS`. `rpc.urls` takes a comma separated list
+    /// replacing the configured endpoints.
+    pub fn set(&mut self, setting: &str, value: &str) -> Result<()> {
+        match setting {
+            "server.listen" => self.server.listen = value.to_string(),
+            "server.workers" => self.server.workers = Some(parse(setting, value)?),
+            "server.templates" => self.server.templates = value.to_string(),
+            "server.log" => self.server.log = value.to_string(),
+            "server.shutdown_timeout_secs" => {
+                self.server.shutdown_timeout_secs = parse(setting, value)?
+            }
+            "rpc.urls" => {
+                self.rpc.endpoints = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|url| !url.is_empty())
+                    .map(|url| HttpSenderConfig {
+                        url: url.to_string(),
+                        ..Default::default()
+                    })
+                    .collect()
+            }
+            "rpc.timeout_secs" => self.rpc.timeout_secs = parse(setting, value)?,
+            "rpc.health_check_interval_secs" => {
+                self.rpc.health_check_interval_secs = parse(setting, value)?
+            }
+            "cache.enabled" => self.cache.enabled = parse_bool(setting, value)?,
+            "cache.account_ttl_secs" => self.cache.account_ttl_secs = parse(setting, value)?,
+            "cache.token_accounts_ttl_secs" => {
+                self.cache.token_accounts_ttl_secs = parse(setting, value)?
+            }
+            "cache.max_entries" => self.cache.max_entries = parse(setting, value)?,
+            "features.compressed_nfts" => {
+                self.features.compressed_nfts = parse_bool(setting, value)?
+            }
+            "features.metadata_proxy" => self.features.metadata_proxy = parse_bool(setting, value)?,
+            _ => return Err(ConfigError::UnknownSetting(setting.to_string())),
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |setting: &str, value: &dyn ToString, reason: &str| {
+            Err(ConfigError::InvalidValue {
+                setting: setting.to_string(),
+                value: value.to_string(),
+                reason: reason.to_string(),
+            })
+        };
+
+        match self.server.listen.to_socket_addrs() {
+            Ok(addrs) if addrs.len() > 0 => {}
+            Ok(_) => return invalid("server.listen", &self.server.listen, "no address"),
+            Err(err) => return invalid("server.listen", &self.server.listen, &err.to_string()),
+        }
+        if self.server.workers == Some(0) {
+            return invalid("server.workers", &0, "must be at least 1");
+        }
+        if self.server.templates.is_empty() {
+            return invalid("server.templates", &"", "must not be empty");
+        }
+        if self.rpc.timeout_secs == 0 {
+            return invalid("rpc.timeout_secs", &0, "must be at least 1");
+        }
+        if self.rpc.endpoints.is_empty() {
+            return Err(ConfigError::Invalid(
+                "at least one rpc endpoint is required".into(),
+            ));
+        }
+
+        // Building the senders checks urls, headers, proxies and limits.
+        self.rpc_senders().map(|_| ())
+    }
+
+    /// One sender per configured endpoint, each behind its own circuit
+    /// breaker and leaving retries to the pool's failover.
+    pub fn rpc_senders(&self) -> Result<Vec<CustomHttpSender>> {
+        let retry_policy = PooledHttpSender::endpoint_retry_policy(self.rpc.endpoints.len());
+        self.rpc
+            .endpoints
+            .iter()
+            .map(|endpoint| {
+                let mut endpoint = endpoint.clone();
+                endpoint.timeout_secs = endpoint.timeout_secs.or(Some(self.rpc.timeout_secs));
+                CustomHttpSenderBuilder::from_config(&endpoint)
+                    .retry_policy(retry_policy.clone())
+                    .circuit_breaker(CircuitBreakerConfig::default())
+                    .build()
+                    .map_err(|err| ConfigError::Invalid(err.to_string()))
+            })
+            .collect()
+    }
+
+    /// Pool over the configured endpoints.
+    pub fn rpc_pool(&self) -> Result<PooledHttpSender> {
+        Ok(PooledHttpSender::from_senders(self.rpc_senders()?))
+    }
+
+    /// Crawler over `pool`, with caching if enabled.
+    pub fn crawler(&self, pool: PooledHttpSender) -> SolanaCrawler {
+        let client = if self.cache.enabled {
+            SolanaClient::new_sender(CachingSender::new(pool, self.cache.sender_config()))
+        } else {
+            SolanaClient::new_sender(pool)
+        };
+        SolanaCrawler::new_client(client)
+    }
+
+    pub fn health_check_interval(&self) -> Option<Duration> {
+        match self.rpc.health_check_interval_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+}
+
+/// Environment variable overriding `setting`.
+fn env_var(setting: &str) -> String {
+    format!("SOLANA_E_{}", setting.replace('.', "_").to_uppercase())
+}
+
+fn parse<T: FromStr>(setting: &str, value: &str) -> Result<T>
+where
+    T::Err: ToString,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|err: T::Err| ConfigError::InvalidValue {
+            setting: setting.to_string(),
+            value: value.to_string(),
+            reason: err.to_string(),
+        })
+}
+
+fn parse_bool(setting: &str, value: &str) -> Result<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(ConfigError::InvalidValue {
+            setting: setting.to_string(),
+            value: value.to_string(),
+            reason: "expected true or false".into(),
+        }),
+    }
+}
+
+fn command() -> Command<'static> {
+    Command::new("solana-e")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about(env!("CARGO_PKG_DESCRIPTION"))
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .short('c')
+                .value_name("FILE")
+                .takes_value(true)
+                .help("TOML configuration file [env: SOLANA_E_CONFIG]"),
+        )
+        .arg(
+            Arg::new("listen")
+                .long("listen")
+                .value_name("ADDR")
+                .takes_value(true)
+                .help("Address to bind, e.g. 0.0.0.0:8081"),
+        )
+        .arg(
+            Arg::new("workers")
+                .long("workers")
+                .value_name("N")
+                .takes_value(true)
+                .help("Number of http workers"),
+        )
+        .arg(
+            Arg::new("rpc-url")
+                .long("rpc-url")
+                .value_name("URL")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("RPC endpoint, repeat for several, replaces the configured ones"),
+        )
+        .arg(
+            Arg::new("rpc-timeout-secs")
+                .long("rpc-timeout-secs")
+                .value_name("SECS")
+                .takes_value(true)
+                .help("Default RPC request timeout"),
+        )
+        .arg(
+            Arg::new("templates")
+                .long("templates")
+                .value_name("GLOB")
+                .takes_value(true)
+                .help("Glob of the Tera templates"),
+        )
+        .arg(
+            Arg::new("log")
+                .long("log")
+                .value_name("FILTER")
+                .takes_value(true)
+                .help("Log filter used when RUST_LOG is not set"),
+        )
+        .arg(
+            Arg::new("cache-max-entries")
+                .long("cache-max-entries")
+                .value_name("N")
+                .takes_value(true)
+                .help("RPC responses cached at most"),
+        )
+        .arg(
+            Arg::new("no-cache")
+                .long("no-cache")
+                .help("Disable the RPC response cache"),
+        )
+        .arg(
+            Arg::new("no-compressed-nfts")
+                .long("no-compressed-nfts")
+                .help("Skip compressed NFT lookups"),
+        )
+        .arg(
+            Arg::new("set")
+                .long("set")
+                .value_name("SETTING=VALUE")
+                .takes_value(true)
+                .multiple_occurrences(true)
+                .help("Override any setting, e.g. --set cache.account_ttl_secs=60"),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_client::rpc_sender::RpcSender;
+    use std::collections::HashMap;
+
+    #[test]
+    fn layers_file_env_and_flags() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [server]
+            listen = "127.0.0.1:9000"
+            workers = 2
+
+            [rpc]
+            timeout_secs = 10
+
+            [[rpc.endpoints]]
+            url = "https://rpc.example.com"
+            auth = { bearer = "secret" }
+
+            [[rpc.endpoints]]
+            url = "https://backup.example.com"
+            timeout_secs = 5
+
+            [cache]
+            max_entries = 100
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.listen, "127.0.0.1:9000");
+        assert_eq!(config.rpc.endpoints.len(), 2);
+        assert!(config.cache.enabled);
+        assert!(config.features.compressed_nfts);
+
+        let env: HashMap<String, String> = [
+            ("SOLANA_E_SERVER_WORKERS".to_string(), "4".to_string()),
+            ("SOLANA_E_CACHE_ENABLED".to_string(), "false".to_string()),
+        ]
+        .into();
+        config.apply_env(|name| env.get(name).cloned()).unwrap();
+        assert_eq!(config.server.workers, Some(4));
+        assert!(!config.cache.enabled);
+
+        let matches = command().get_matches_from([
+            "solana-e",
+            "--listen",
+            "127.0.0.1:9001",
+            "--no-compressed-nfts",
+            "--set",
+            "cache.account_ttl_secs=60",
+        ]);
+        config.apply_args(&matches).unwrap();
+        assert_eq!(config.server.listen, "127.0.0.1:9001");
+        assert!(!config.features.compressed_nfts);
+        assert_eq!(config.cache.account_ttl_secs, 60);
+
+        config.validate().unwrap();
+        let senders = config.rpc_senders().unwrap();
+        assert_eq!(senders[1].url(), "https://backup.example.com");
+        // The pool fails over instead of retrying.
+        assert!(senders.iter().all(|x| x.retry_policy().max_attempts == 1));
+
+        let matches = command().get_matches_from([
+            "solana-e",
+            "--rpc-url",
+            "http://a",
+            "--rpc-url",
+            "http://b",
+        ]);
+        config.apply_args(&matches).unwrap();
+        let urls: Vec<&str> = config
+            .rpc
+            .endpoints
+            .iter()
+            .map(|x| x.url.as_str())
+            .collect();
+        assert_eq!(urls, ["http://a", "http://b"]);
+    }
+
+    #[test]
+    fn parses_example_config() {
+        let example = include_str!("../../../solana-e.example.toml");
+        let config: Config = toml::from_str(example).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.rpc_senders().unwrap().len(), 2);
+
+        // Without a config file only the public endpoint is used, as is.
+        let defaults = Config::default();
+        assert_eq!(defaults.rpc.endpoints.len(), 1);
+        assert!(defaults.rpc.endpoints[0].headers.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert!(toml::from_str::<Config>("[server]\nlisten_addr = \"0.0.0.0:80\"").is_err());
+        assert!(
+            toml::from_str::<Config>("[[rpc.endpoints]]\nurl = \"http://a\"\ntimeout = 3").is_err()
+        );
+
+        let mut config = Config::default();
+        assert!(matches!(
+            config.set("server.port", "80"),
+            Err(ConfigError::UnknownSetting(_))
+        ));
+        assert!(matches!(
+            config.set("server.workers", "many"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.set("cache.enabled", "maybe"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+
+        config.validate().unwrap();
+        for (setting, value) in [
+            ("server.listen", "nowhere"),
+            ("server.workers", "0"),
+            ("rpc.timeout_secs", "0"),
+            ("rpc.urls", ""),
+            ("rpc.urls", "not a url"),
+        ] {
+            let mut config = Config::default();
+            config.set(setting, value).unwrap();
+            assert!(config.validate().is_err(), "{} = {:?}", setting, value);
+        }
+    }
+}
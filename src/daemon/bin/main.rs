@@ -6,12 +6,24 @@ use actix_web::{
     middleware::{self, ErrorHandlerResponse, ErrorHandlers},
     web, App, Error, HttpResponse, HttpServer, Result,
 };
+use config::Config;
 use dotenv::dotenv;
 use serde::Serialize;
-use solana_e::crawler::SolanaCrawler;
-use std::{collections::HashMap, env};
+use solana_e::{
+    crawler::{CollectionGroup, NftQuery, SolanaCrawler, WalletNfts},
+    error::CrawlerError,
+};
+use std::{collections::HashMap, env, process};
 use tera::Tera;
 
+mod api;
+mod config;
+mod openapi;
+
+/// NFTs per page of a listing when the request doesn't say.
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
 #[get("/")]
 async fn index(tmpl: web::Data<Tera>) -> Result<HttpResponse, Error> {
     let s = tmpl
@@ -21,13 +33,8 @@ async fn index(tmpl: web::Data<Tera>) -> Result<HttpResponse, Error> {
 }
 
 #[get("/solana_version")]
-async fn solana_version() -> Result<HttpResponse, Error> {
-    let url = "https://solitary-white-violet.solana-mainnet.quiknode.pro/";
-    let crawler = SolanaCrawler::new(url);
-    let res = crawler
-        .get_version()
-        .await
-        .expect("solana_version: fatal error on fetching solana version.");
+async fn solana_version(crawler: web::Data<SolanaCrawler>) -> Result<HttpResponse, Error> {
+    let res = crawler.get_version().await.map_err(crawler_error)?;
 
     #[derive(Serialize)]
     struct SolanaVersion {
@@ -63,10 +70,10 @@ async fn load_metadata(query: web::Query<HashMap<String, String>>) -> Result<Htt
 
     let metadata_body = reqwest::get(metadata_uri)
         .await
-        .map_err(|err| error::ErrorInternalServerError(err))?
+        .map_err(error::ErrorInternalServerError)?
         .text()
         .await
-        .map_err(|err| error::ErrorInternalServerError(err))?;
+        .map_err(error::ErrorInternalServerError)?;
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::json())
@@ -76,7 +83,10 @@ async fn load_metadata(query: web::Query<HashMap<String, String>>) -> Result<Htt
 #[get("/wallet")]
 async fn wallet(
     tmpl: web::Data<Tera>,
+    crawler: web::Data<SolanaCrawler>,
+    config: web::Data<Config>,
     query: web::Query<HashMap<String, String>>,
+    nft_query: web::Query<NftQuery>,
 ) -> Result<HttpResponse, Error> {
     let account = query
         .get("account")
@@ -84,14 +94,70 @@ async fn wallet(
             "Failed to load template error",
         ))
         .map(|x| x.as_str())?;
+    let grouped = query.get("group").map(|x| x.as_str()) == Some("collection");
+    let nft_query = page_query(nft_query.into_inner());
 
-    let url = "https://solitary-white-violet.solana-mainnet.quiknode.pro/";
-    let crawler = SolanaCrawler::new(url);
-    let tokens = crawler.get_nfts_for_owner(account).await.unwrap_or(vec![]);
+    // One scan of the owner's token accounts feeds both the NFTs and the
+    // balances.
+    let wallet = crawler.get_wallet(account).await.map_err(crawler_error)?;
+    let scan = WalletNfts {
+        tokens: wallet.nfts,
+        warnings: vec![],
+    };
+    let scan = add_compressed_nfts(&crawler, &config, account, scan).await;
+    let portfolio = wallet.portfolio;
+
+    let warnings: Vec<String> = portfolio
+        .warnings
+        .iter()
+        .chain(scan.warnings.iter())
+        .map(|x| x.to_string())
+        .collect();
 
     let mut ctx = tera::Context::new();
+    ctx.insert("account", account);
+    ctx.insert("sol_balance", &portfolio.sol());
+    ctx.insert("balances", &portfolio.tokens);
+
+    // Grouped pages count collections rather than tokens, so the whole
+    // listing is grouped before a page of groups is cut out of it.
+    let tokens = nft_query.select(scan.tokens);
     ctx.insert("tokens_len", &tokens.len());
-    ctx.insert("tokens", &tokens);
+    let (page_offset, page_len, next_offset) = if grouped {
+        let groups = crawler
+            .group_by_collection(tokens)
+            .await
+            .map_err(crawler_error)?;
+        let page = nft_query.page(groups);
+        ctx.insert("groups_len", &page.total);
+        ctx.insert("groups", &page.items);
+        (page.offset, page.items.len(), page.next_offset)
+    } else {
+        let page = nft_query.page(tokens);
+        ctx.insert("groups", &[serde_json::json!({ "tokens": page.items })]);
+        (page.offset, page.items.len(), page.next_offset)
+    };
+
+    let limit = nft_query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if page_offset > 0 {
+        let offset = page_offset.saturating_sub(limit);
+        ctx.insert(
+            "prev_url",
+            &wallet_url(account, grouped, &nft_query, offset),
+        );
+    }
+    if let Some(offset) = next_offset {
+        ctx.insert(
+            "next_url",
+            &wallet_url(account, grouped, &nft_query, offset),
+        );
+    }
+    ctx.insert("page_start", &(page_offset + 1));
+    ctx.insert("page_end", &(page_offset + page_len));
+    ctx.insert("query", &nft_query);
+    ctx.insert("warnings", &warnings);
+    ctx.insert("grouped", &grouped);
+
     let body = tmpl
         .render("wallet.html", &ctx)
         .map_err(|_| error::ErrorInternalServerError("Failed to load template error"))?;
@@ -101,38 +167,193 @@ async fn wallet(
         .body(body))
 }
 
+#[get("/wallet_collections")]
+async fn wallet_collections(
+    crawler: web::Data<SolanaCrawler>,
+    config: web::Data<Config>,
+    query: web::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, Error> {
+    let account = query
+        .get("account")
+        .ok_or(error::ErrorBadRequest("Missing account"))
+        .map(|x| x.as_str())?;
+
+    let scan = load_wallet(&crawler, &config, account)
+        .await
+        .map_err(crawler_error)?;
+    let groups = crawler
+        .group_by_collection(scan.tokens)
+        .await
+        .map_err(crawler_error)?;
+
+    #[derive(Serialize)]
+    struct WalletCollections {
+        account: String,
+        tokens_len: usize,
+        collections: Vec<CollectionGroup>,
+        warnings: Vec<String>,
+    }
+
+    let obj = WalletCollections {
+        account: account.to_string(),
+        tokens_len: groups.iter().map(|x| x.count).sum(),
+        collections: groups,
+        warnings: scan.warnings.iter().map(|x| x.to_string()).collect(),
+    };
+
+    Ok(HttpResponse::Ok().json(obj))
+}
+
+/// `query` with its page size defaulted and capped.
+fn page_query(query: NftQuery) -> NftQuery {
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    NftQuery {
+        limit: Some(limit.clamp(1, MAX_PAGE_SIZE)),
+        ..query
+    }
+}
+
+/// Link to the wallet page of `account` showing `query` from `offset`.
+fn wallet_url(account: &str, grouped: bool, query: &NftQuery, offset: usize) -> String {
+    #[derive(Serialize)]
+    struct WalletParams<'a> {
+        account: &'a str,
+        group: Option<&'a str>,
+        #[serde(flatten)]
+        query: NftQuery,
+    }
+
+    let params = WalletParams {
+        account,
+        group: grouped.then_some("collection"),
+        query: NftQuery {
+            offset,
+            ..query.clone()
+        },
+    };
+    let params = serde_urlencoded::to_string(params).unwrap_or_default();
+    format!("/wallet?{}", params)
+}
+
+/// Loads both the SPL and, unless disabled, the compressed NFTs held by
+/// `account`.
+async fn load_wallet(
+    crawler: &SolanaCrawler,
+    config: &Config,
+    account: &str,
+) -> Result<WalletNfts, CrawlerError> {
+    let scan = crawler.get_nfts_for_owner(account).await?;
+    Ok(add_compressed_nfts(crawler, config, account, scan).await)
+}
+
+/// Adds the compressed NFTs of `account` to `scan`, unless disabled.
+async fn add_compressed_nfts(
+    crawler: &SolanaCrawler,
+    config: &Config,
+    account: &str,
+    mut scan: WalletNfts,
+) -> WalletNfts {
+    if !config.features.compressed_nfts {
+        return scan;
+    }
+
+    // Not every endpoint implements the DAS api, so a failure here only
+    // hides compressed NFTs instead of failing the whole page.
+    match crawler.get_compressed_nfts_for_owner(account).await {
+        Ok(compressed) => {
+            scan.tokens.extend(compressed.tokens);
+            scan.warnings.extend(compressed.warnings);
+        }
+        Err(err) => {
+            log::warn!("wallet: failed to load compressed nfts: {}", err);
+            scan.warnings.push(err);
+        }
+    }
+
+    scan
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
 
+    let config = Config::load().unwrap_or_else(|err| {
+        eprintln!("solana-e: {}", err);
+        process::exit(2);
+    });
+
     if env::var("RUST_LOG").is_err() {
-        env::set_var("RUST_LOG", "solana_e=trace");
+        env::set_var("RUST_LOG", &config.server.log);
     }
 
     tracing_subscriber::fmt()
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
-    log::info!("Listening on 0.0.0.0:8081!");
+    log::info!("Loading template from path {}", config.server.templates);
+    let tera = Tera::new(&config.server.templates).unwrap_or_else(|err| {
+        eprintln!("solana-e: failed to load templates: {}", err);
+        process::exit(2);
+    });
+
+    // Shared by every worker, so that connections, rate limits, circuit
+    // breakers and the cache outlive single requests.
+    let pool = config.rpc_pool().unwrap_or_else(|err| {
+        eprintln!("solana-e: {}", err);
+        process::exit(2);
+    });
+    let _health_checks = config
+        .health_check_interval()
+        .map(|interval| pool.spawn_health_checks(interval));
+    let crawler = web::Data::new(config.crawler(pool));
+    let tera = web::Data::new(tera);
+
+    log::info!("Listening on {}!", config.server.listen);
 
-    let template_path = concat!(env!("CARGO_MANIFEST_DIR"), "/templates/**/*");
-    log::info!("Loading template from path {}", template_path);
+    let listen = config.server.listen.clone();
+    let workers = config.server.workers;
+    let shutdown_timeout = config.server.shutdown_timeout_secs;
+    let config = web::Data::new(config);
 
-    HttpServer::new(|| {
-        let tera = Tera::new(template_path).unwrap();
+    let mut server = HttpServer::new(move || {
+        let metadata_proxy = config.features.metadata_proxy;
 
         App::new()
-            .app_data(web::Data::new(tera))
+            .app_data(tera.clone())
+            .app_data(crawler.clone())
+            .app_data(config.clone())
             .wrap(middleware::Logger::default())
             .service(index)
             .service(wallet)
-            .service(load_metadata)
+            .service(wallet_collections)
+            .configure(|cfg| {
+                if metadata_proxy {
+                    cfg.service(load_metadata);
+                }
+            })
             .service(solana_version)
+            .configure(api::configure)
+            .configure(openapi::configure)
             .service(web::scope("").wrap(error_handlers()))
     })
-    .bind(("0.0.0.0", 8081))?
-    .run()
-    .await
+    .shutdown_timeout(shutdown_timeout);
+    if let Some(workers) = workers {
+        server = server.workers(workers);
+    }
+
+    server.bind(listen)?.run().await
+}
+
+fn crawler_error(err: CrawlerError) -> Error {
+    log::error!("crawler: {}", err);
+    match err {
+        CrawlerError::InvalidAddress(_) => error::ErrorBadRequest(err.to_string()),
+        CrawlerError::AccountNotFound(_) => error::ErrorNotFound(err.to_string()),
+        CrawlerError::RpcTransport(_) | CrawlerError::RpcResponse(_) => {
+            error::ErrorBadGateway(err.to_string())
+        }
+        _ => error::ErrorInternalServerError(err.to_string()),
+    }
 }
 
 fn error_handlers() -> ErrorHandlers<BoxBody> {
@@ -175,3 +396,33 @@ fn get_error_response<B>(res: &ServiceResponse<B>, error: &str) -> HttpResponse
         None => fallback(error),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_e::crawler::NftSort;
+
+    #[test]
+    fn builds_wallet_page_links() {
+        let query = page_query(NftQuery {
+            symbol: Some("A&B".into()),
+            sort: Some(NftSort::Collection),
+            limit: Some(10_000),
+            ..Default::default()
+        });
+        assert_eq!(query.limit, Some(MAX_PAGE_SIZE));
+        assert_eq!(
+            page_query(NftQuery::default()).limit,
+            Some(DEFAULT_PAGE_SIZE)
+        );
+
+        assert_eq!(
+            wallet_url("wallet", true, &query, 500),
+            "/wallet?account=wallet&group=collection&symbol=A%26B&sort=collection&order=asc&offset=500&limit=500"
+        );
+        assert_eq!(
+            wallet_url("wallet", false, &NftQuery::default(), 0),
+            "/wallet?account=wallet&order=asc&offset=0"
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use actix_web::web;
+use utoipa::OpenApi;
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::api;
+
+macro_rules! api_doc {
+    ($($handler:ident),*) => {
+        /// OpenAPI document of the `/api/v1` routes, generated from the
+        /// handlers' `#[utoipa::path]` attributes and the schemas of the types
+        /// they return.
+        #[derive(OpenApi)]
+        #[openapi(paths($(api::$handler),*))]
+        pub struct ApiDoc;
+    };
+}
+
+api::handlers!(api_doc);
+
+/// Registers `/api/openapi.json` and the Swagger UI rendering it under
+/// `/api/docs/`, served from assets built into the binary.
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::redirect("/api/docs", "/api/docs/"))
+        .service(SwaggerUi::new("/api/docs/{_:.*}").url("/api/openapi.json", ApiDoc::openapi()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::tests::replay_app_data;
+    use actix_web::{
+        http::StatusCode,
+        test::{call_and_read_body_json, call_service, init_service, read_body_json, TestRequest},
+        App,
+    };
+    use serde_json::{json, Value};
+    use solana_e::crawler::{
+        EditionKind, TokenClass, TokenCollection, TokenCreator, TokenEdition, TokenMetadata,
+        TokenUses,
+    };
+    use std::collections::BTreeSet;
+
+    const WALLET: &str = "4Qkev8aNZcqFNSRhQzwyLMFSsi94jHqE8WNVTJzTP99F";
+    const NFT: &str = "7TENEjB8Aw1jC2BpNHAHFe7HRCBx3ZiKTXbDqjmkrvSb";
+
+    fn spec() -> Value {
+        serde_json::to_value(ApiDoc::openapi()).unwrap()
+    }
+
+    /// Checks `value` against `schema`, resolving references in `spec`.
+    fn check(spec: &Value, schema: &Value, value: &Value, at: &str) -> Result<(), String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            let schema = &spec["components"]["schemas"][name];
+            if !schema.is_object() {
+                return Err(format!("{}: unknown schema {}", at, name));
+            }
+            return check(spec, schema, value, at);
+        }
+        if let Some(one_of) = schema["oneOf"].as_array() {
+            let matching = one_of
+                .iter()
+                .filter(|x| check(spec, x, value, at).is_ok())
+                .count();
+            return match matching {
+                1 => Ok(()),
+                _ => Err(format!("{}: {} matches {} variants", at, value, matching)),
+            };
+        }
+
+        let types: Vec<&str> = match &schema["type"] {
+            Value::String(x) => vec![x.as_str()],
+            Value::Array(x) => x.iter().filter_map(Value::as_str).collect(),
+            other => return Err(format!("{}: unsupported type {}", at, other)),
+        };
+        let found = match value {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(x) if x.is_f64() => "number",
+            Value::Number(_) if types.contains(&"number") => "number",
+            Value::Number(_) => "integer",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        };
+        if !types.contains(&found) {
+            return Err(format!("{}: expected {:?}, got {}", at, types, value));
+        }
+
+        match value {
+            Value::String(_) => match schema["enum"].as_array() {
+                Some(values) if !values.contains(value) => {
+                    Err(format!("{}: unexpected {}", at, value))
+                }
+                _ => Ok(()),
+            },
+            Value::Array(items) => items.iter().enumerate().try_for_each(|(index, item)| {
+                check(spec, &schema["items"], item, &format!("{}[{}]", at, index))
+            }),
+            Value::Object(object) => {
+                let properties = schema["properties"].as_object().unwrap();
+                for name in schema["required"].as_array().into_iter().flatten() {
+                    if !object.contains_key(name.as_str().unwrap()) {
+                        return Err(format!("{}: missing {}", at, name));
+                    }
+                }
+                object
+                    .iter()
+                    .try_for_each(|(name, value)| match properties.get(name) {
+                        Some(property) => check(spec, property, value, &format!("{}.{}", at, name)),
+                        None => Err(format!("{}: undocumented {}", at, name)),
+                    })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    #[test]
+    fn describes_complete_metadata() {
+        let spec = spec();
+        let token = TokenMetadata {
+            creators: vec![TokenCreator {
+                address: WALLET.into(),
+                verified: true,
+                share: 100,
+            }],
+            collection: Some(TokenCollection {
+                key: NFT.into(),
+                verified: true,
+            }),
+            edition_nonce: Some(255),
+            token_standard: Some("NonFungible".into()),
+            uses: Some(TokenUses {
+                use_method: "Burn".into(),
+                remaining: 1,
+                total: 1,
+            }),
+            edition: Some(TokenEdition {
+                kind: EditionKind::Print,
+                number: Some(2),
+                supply: Some(1),
+                max_supply: Some(10),
+                parent: Some(NFT.into()),
+            }),
+            class: Some(TokenClass::Nft),
+            ..Default::default()
+        };
+        let schema = json!({"$ref": "#/components/schemas/TokenMetadata"});
+        check(
+            &spec,
+            &schema,
+            &serde_json::to_value(token).unwrap(),
+            "TokenMetadata",
+        )
+        .unwrap();
+
+        let mut token = serde_json::to_value(TokenMetadata::default()).unwrap();
+        token["class"] = "fungible".into();
+        assert!(check(&spec, &schema, &token, "TokenMetadata").is_err());
+    }
+
+    #[test]
+    fn documents_page_size() {
+        let spec = spec();
+        let limit = spec["paths"]["/api/v1/wallets/{address}/nfts"]["get"]["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|x| x["name"] == "limit")
+            .unwrap();
+        let description = limit["description"].as_str().unwrap();
+        assert!(description.contains(&format!(" {} ", crate::DEFAULT_PAGE_SIZE)));
+        assert!(description.contains(&format!(" {}.", crate::MAX_PAGE_SIZE)));
+    }
+
+    #[actix_web::test]
+    async fn matches_api_responses() {
+        let (crawler, config) = replay_app_data();
+        let app = init_service(
+            App::new()
+                .app_data(crawler)
+                .app_data(config)
+                .configure(api::configure)
+                .configure(configure),
+        )
+        .await;
+
+        let served: Value = call_and_read_body_json(
+            &app,
+            TestRequest::get().uri("/api/openapi.json").to_request(),
+        )
+        .await;
+        let spec = spec();
+        assert_eq!(served, spec);
+
+        macro_rules! names {
+            ($($handler:ident),*) => {
+                BTreeSet::from([$(stringify!($handler)),*])
+            };
+        }
+        let documented: BTreeSet<_> = spec["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|item| item["get"]["operationId"].as_str().unwrap())
+            .collect();
+        assert_eq!(documented, api::handlers!(names));
+
+        for (path, item) in spec["paths"].as_object().unwrap() {
+            assert!(path.starts_with(api::SCOPE), "{}", path);
+            let responses = &item["get"]["responses"];
+            let uris = [
+                path.replace("{address}", WALLET).replace("{mint}", NFT),
+                path.replace("{address}", "not-an-address")
+                    .replace("{mint}", "not-an-address"),
+            ];
+            for uri in uris {
+                let response = call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+                // The router resolves the request to the documented template,
+                // not to the scope's fallback.
+                assert_eq!(
+                    response.request().match_pattern().as_deref(),
+                    Some(path.as_str()),
+                    "{}",
+                    uri
+                );
+                let status = response.status().as_u16().to_string();
+                let body: Value = read_body_json(response).await;
+
+                let documented = &responses[&status];
+                assert!(documented.is_object(), "{}: undocumented {}", uri, status);
+                check(
+                    &spec,
+                    &documented["content"]["application/json"]["schema"],
+                    &body,
+                    &uri,
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn serves_vendored_docs() {
+        let app = init_service(App::new().configure(configure)).await;
+
+        let response = call_service(&app, TestRequest::get().uri("/api/docs").to_request()).await;
+        assert!(response.status().is_redirection());
+
+        for uri in ["/api/docs/", "/api/docs/swagger-ui.css"] {
+            let response = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        }
+        let response = call_service(
+            &app,
+            TestRequest::get().uri("/api/docs/index.html").to_request(),
+        )
+        .await;
+        let page = actix_web::test::read_body(response).await;
+        let page = String::from_utf8_lossy(&page);
+        assert!(!page.contains("https://"), "docs load remote assets");
+    }
+}
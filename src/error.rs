@@ -0,0 +1,69 @@
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, CrawlerError>;
+
+#[derive(Debug, Error)]
+pub enum CrawlerError {
+    #[error("invalid address `{0}`")]
+    InvalidAddress(String),
+
+    #[error("rpc transport error: {0}")]
+    RpcTransport(Box<ClientError>),
+
+    #[error("rpc response error: {0}")]
+    RpcResponse(Box<ClientError>),
+
+    #[error("failed to decode metadata for mint {mint}: {reason}")]
+    MetadataDecode { mint: String, reason: String },
+
+    #[error("unsupported account type: {0}")]
+    UnsupportedAccount(String),
+
+    #[error("account not found: {0}")]
+    AccountNotFound(String),
+
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+}
+
+impl CrawlerError {
+    pub fn metadata_decode<M: ToString, R: ToString>(mint: M, reason: R) -> Self {
+        Self::MetadataDecode {
+            mint: mint.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl From<ClientError> for CrawlerError {
+    fn from(err: ClientError) -> Self {
+        match err.kind() {
+            ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_) => {
+                Self::RpcTransport(Box::new(err))
+            }
+            _ => Self::RpcResponse(Box::new(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_client::rpc_request::RpcError;
+
+    #[test]
+    fn classifies_client_errors() {
+        let io = ClientError::from(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        assert!(matches!(
+            CrawlerError::from(io),
+            CrawlerError::RpcTransport(_)
+        ));
+
+        let rpc = ClientError::from(RpcError::ForUser("bad params".into()));
+        assert!(matches!(
+            CrawlerError::from(rpc),
+            CrawlerError::RpcResponse(_)
+        ));
+    }
+}
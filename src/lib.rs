@@ -1,4 +1,5 @@
 pub mod crawler;
+pub mod error;
 pub mod rpc;
 
 pub fn add(left: usize, right: usize) -> usize {
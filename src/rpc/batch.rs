@@ -0,0 +1,24 @@
+use async_trait::async_trait;
+use futures::future::join_all;
+use solana_client::{client_error, rpc_request::RpcRequest, rpc_sender::RpcSender};
+
+pub type BatchRequest = (RpcRequest, serde_json::Value);
+
+/// Result of every request of a batch, in request order.
+pub type BatchResponse = Vec<client_error::Result<serde_json::Value>>;
+
+/// Transports able to send several JSON-RPC calls in one round trip.
+#[async_trait]
+pub trait RpcBatchSender: RpcSender + Send + Sync {
+    /// Sends `requests` and returns one result per request. The outer error
+    /// is a failure of the whole batch, e.g. a transport error.
+    ///
+    /// The default implementation sends the requests one by one,
+    /// concurrently.
+    async fn send_batch(&self, requests: Vec<BatchRequest>) -> client_error::Result<BatchResponse> {
+        let responses = requests
+            .into_iter()
+            .map(|(request, params)| self.send(request, params));
+        Ok(join_all(responses).await)
+    }
+}
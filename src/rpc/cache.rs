@@ -0,0 +1,350 @@
+use async_trait::async_trait;
+use solana_client::{client_error, rpc_request::RpcRequest, rpc_sender::*};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::batch::{BatchRequest, BatchResponse, RpcBatchSender};
+
+#[derive(Clone, Copy, Debug)]
+pub struct CacheConfig {
+    /// Lifetime of `getAccountInfo` and `getMultipleAccounts` responses.
+    pub account_ttl: Duration,
+    /// Lifetime of `getTokenAccountsByOwner` responses.
+    pub token_accounts_ttl: Duration,
+    /// Responses kept at most, the oldest being evicted first.
+    pub max_entries: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            account_ttl: Duration::from_secs(30),
+            token_accounts_ttl: Duration::from_secs(10),
+            max_entries: 10_000,
+        }
+    }
+}
+
+impl CacheConfig {
+    fn ttl(&self, request: RpcRequest) -> Option<Duration> {
+        match request {
+            RpcRequest::GetAccountInfo | RpcRequest::GetMultipleAccounts => Some(self.account_ttl),
+            RpcRequest::GetTokenAccountsByOwner => Some(self.token_accounts_ttl),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// Method and params, without `minContextSlot`.
+type CacheKey = (String, String);
+
+struct Entry {
+    value: serde_json::Value,
+    /// Slot of the response context.
+    slot: Option<u64>,
+    expires: Instant,
+    generation: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    entries: HashMap<CacheKey, Entry>,
+    /// Insertion order, for eviction. Keys whose generation no longer
+    /// matches their entry were replaced since.
+    order: VecDeque<(CacheKey, u64)>,
+    generation: u64,
+}
+
+/// Caches account lookups of the wrapped sender. Requests carrying a
+/// `minContextSlot` are only served from responses at least that recent.
+pub struct CachingSender<S> {
+    inner: S,
+    config: CacheConfig,
+    entries: Mutex<Entries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl<S> CachingSender<S> {
+    pub fn new(inner: S, config: CacheConfig) -> Self {
+        Self {
+            inner,
+            config,
+            entries: Mutex::new(Entries::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().entries.len(),
+        }
+    }
+
+    pub fn clear(&self) {
+        *self.entries.lock().unwrap() = Entries::default();
+    }
+
+    fn get(&self, key: &CacheKey, min_context_slot: Option<u64>) -> Option<serde_json::Value> {
+        let mut entries = self.entries.lock().unwrap();
+        let value = match entries.entries.get(key) {
+            Some(entry) if entry.expires <= Instant::now() => {
+                entries.entries.remove(key);
+                None
+            }
+            Some(entry) if entry.slot < min_context_slot => None,
+            Some(entry) => Some(entry.value.clone()),
+            None => None,
+        };
+
+        match value {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        value
+    }
+
+    fn insert(&self, key: CacheKey, ttl: Duration, value: &serde_json::Value) {
+        if self.config.max_entries == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.generation += 1;
+        let generation = entries.generation;
+        entries.order.push_back((key.clone(), generation));
+        entries.entries.insert(
+            key,
+            Entry {
+                value: value.clone(),
+                slot: value["context"]["slot"].as_u64(),
+                expires: Instant::now() + ttl,
+                generation,
+            },
+        );
+
+        while entries.entries.len() > self.config.max_entries {
+            let (key, generation) = match entries.order.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if entries.entries.get(&key).map(|x| x.generation) == Some(generation) {
+                entries.entries.remove(&key);
+            }
+        }
+        if entries.order.len() > self.config.max_entries * 2 {
+            let Entries { entries, order, .. } = &mut *entries;
+            order.retain(|(key, generation)| {
+                entries.get(key).map(|x| x.generation) == Some(*generation)
+            });
+        }
+    }
+}
+
+/// Cache key of `params`, and the `minContextSlot` it asks for if any.
+fn cache_key(request: RpcRequest, params: &serde_json::Value) -> (CacheKey, Option<u64>) {
+    let mut params = params.clone();
+    let mut min_context_slot = None;
+    if let Some(items) = params.as_array_mut() {
+        for config in items.iter_mut().filter_map(|x| x.as_object_mut()) {
+            if let Some(slot) = config.remove("minContextSlot") {
+                min_context_slot = slot.as_u64();
+            }
+        }
+    }
+
+    ((request.to_string(), params.to_string()), min_context_slot)
+}
+
+#[async_trait]
+impl<S: RpcSender + Send + Sync> RpcSender for CachingSender<S> {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> client_error::Result<serde_json::Value> {
+        let ttl = match self.config.ttl(request) {
+            Some(ttl) => ttl,
+            None => return self.inner.send(request, params).await,
+        };
+
+        let (key, min_context_slot) = cache_key(request, &params);
+        if let Some(value) = self.get(&key, min_context_slot) {
+            return Ok(value);
+        }
+
+        let value = self.inner.send(request, params).await?;
+        self.insert(key, ttl, &value);
+        Ok(value)
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+#[async_trait]
+impl<S: RpcBatchSender> RpcBatchSender for CachingSender<S> {
+    /// Serves what it can from the cache and batches the rest.
+    async fn send_batch(&self, requests: Vec<BatchRequest>) -> client_error::Result<BatchResponse> {
+        let mut responses: Vec<Option<client_error::Result<serde_json::Value>>> =
+            Vec::with_capacity(requests.len());
+        let mut misses = vec![];
+        for (index, (request, params)) in requests.into_iter().enumerate() {
+            let cacheable = self.config.ttl(request).map(|ttl| {
+                let (key, min_context_slot) = cache_key(request, &params);
+                (key, min_context_slot, ttl)
+            });
+            if let Some((key, min_context_slot, _)) = &cacheable {
+                if let Some(value) = self.get(key, *min_context_slot) {
+                    responses.push(Some(Ok(value)));
+                    continue;
+                }
+            }
+
+            responses.push(None);
+            let key = cacheable.map(|(key, _, ttl)| (key, ttl));
+            misses.push((index, key, (request, params)));
+        }
+
+        if !misses.is_empty() {
+            let requests = misses
+                .iter()
+                .map(|(_, _, request)| request.clone())
+                .collect();
+            let results = self.inner.send_batch(requests).await?;
+            for ((index, key, _), result) in misses.into_iter().zip(results) {
+                if let (Some((key, ttl)), Ok(value)) = (key, &result) {
+                    self.insert(key, ttl, value);
+                }
+                responses[index] = Some(result);
+            }
+        }
+
+        // Every item is either a hit or one of the misses answered above.
+        Ok(responses.into_iter().flatten().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::cassette::{Interaction, ReplaySender};
+
+    fn account_info(slot: u64, min_context_slot: Option<u64>) -> Interaction {
+        let mut config = serde_json::json!({"encoding": "base64"});
+        if let Some(min_context_slot) = min_context_slot {
+            config["minContextSlot"] = min_context_slot.into();
+        }
+        Interaction {
+            method: "getAccountInfo".into(),
+            params: serde_json::json!(["11111111111111111111111111111111", config]),
+            result: Some(serde_json::json!({"context": {"slot": slot}, "value": null})),
+            error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_repeated_account_lookups() {
+        let replay = ReplaySender::from_interactions(vec![
+            account_info(100, None),
+            account_info(200, Some(150)),
+        ]);
+        let sender = CachingSender::new(replay, CacheConfig::default());
+        let params = account_info(0, None).params;
+
+        let first = sender
+            .send(RpcRequest::GetAccountInfo, params.clone())
+            .await
+            .unwrap();
+        let second = sender
+            .send(RpcRequest::GetAccountInfo, params.clone())
+            .await
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(sender.get_transport_stats().request_count, 1);
+
+        // Too old for the requested slot, refetched.
+        let newer = account_info(0, Some(150)).params;
+        let third = sender
+            .send(RpcRequest::GetAccountInfo, newer)
+            .await
+            .unwrap();
+        assert_eq!(third["context"]["slot"], 200);
+        assert_eq!(sender.get_transport_stats().request_count, 2);
+
+        let batch = sender
+            .send_batch(vec![
+                (RpcRequest::GetAccountInfo, params.clone()),
+                (RpcRequest::GetAccountInfo, params),
+            ])
+            .await
+            .unwrap();
+        assert!(batch.iter().all(|x| x.is_ok()));
+        assert_eq!(sender.get_transport_stats().request_count, 2);
+        assert_eq!(
+            sender.stats(),
+            CacheStats {
+                hits: 3,
+                misses: 2,
+                entries: 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn expires_and_bounds_entries() {
+        let replay = ReplaySender::from_interactions(vec![account_info(100, None)]);
+        let config = CacheConfig {
+            account_ttl: Duration::ZERO,
+            ..Default::default()
+        };
+        let sender = CachingSender::new(replay, config);
+        for _ in 0..2 {
+            sender
+                .send(RpcRequest::GetAccountInfo, account_info(0, None).params)
+                .await
+                .unwrap();
+        }
+        assert_eq!(sender.get_transport_stats().request_count, 2);
+
+        let sender = CachingSender::new(
+            ReplaySender::from_interactions(vec![]),
+            CacheConfig {
+                max_entries: 2,
+                ..Default::default()
+            },
+        );
+        for slot in 0..5 {
+            let key = (format!("method{}", slot), String::new());
+            sender.insert(key, Duration::from_secs(60), &serde_json::Value::Null);
+        }
+        assert_eq!(sender.stats().entries, 2);
+        assert!(sender
+            .get(&("method4".into(), String::new()), None)
+            .is_some());
+        assert!(sender
+            .get(&("method0".into(), String::new()), None)
+            .is_none());
+    }
+}
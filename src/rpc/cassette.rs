@@ -0,0 +1,347 @@
+//! Record and replay of RPC traffic, so the crawler can be exercised against
+//! real responses without a network connection.
+//!
+//! A cassette is a JSONL file holding one `Interaction` per line.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use solana_client::{
+    client_error::{self, ClientError, ClientErrorKind},
+    rpc_request::{RpcError, RpcRequest, RpcResponseErrorData},
+    rpc_sender::*,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+    sync::{Mutex, RwLock},
+};
+
+use super::batch::{BatchRequest, BatchResponse, RpcBatchSender};
+use super::custom_http_sender::CustomHttpSender;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Interaction {
+    pub method: String,
+    pub params: serde_json::Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RecordedError>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordedError {
+    #[serde(default)]
+    pub kind: RecordedErrorKind,
+    /// JSON-RPC error code of `Response` errors.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<i64>,
+    pub message: String,
+}
+
+/// How a recorded request failed, so the replayed error classifies the same
+/// way, see `CrawlerError::from` and `is_endpoint_failure`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordedErrorKind {
+    /// JSON-RPC error object returned by the node.
+    Response,
+    /// The request never got an answer: connection, timeout or HTTP errors.
+    Transport,
+    /// Anything else, such as a response that failed to decode.
+    #[default]
+    Other,
+}
+
+impl Interaction {
+    fn new(
+        request: RpcRequest,
+        params: serde_json::Value,
+        result: &client_error::Result<serde_json::Value>,
+    ) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result.clone()), None),
+            Err(err) => (None, Some(RecordedError::from(err))),
+        };
+
+        Self {
+            method: request.to_string(),
+            params,
+            result,
+            error,
+        }
+    }
+
+    fn key(&self) -> (String, String) {
+        (self.method.clone(), self.params.to_string())
+    }
+}
+
+impl From<&ClientError> for RecordedError {
+    fn from(err: &ClientError) -> Self {
+        let kind = match err.kind() {
+            ClientErrorKind::RpcError(RpcError::RpcResponseError { code, message, .. }) => {
+                return Self {
+                    kind: RecordedErrorKind::Response,
+                    code: Some(*code),
+                    message: message.clone(),
+                }
+            }
+            ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_) => RecordedErrorKind::Transport,
+            _ => RecordedErrorKind::Other,
+        };
+
+        Self {
+            kind,
+            code: None,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl From<RecordedError> for ClientError {
+    fn from(err: RecordedError) -> Self {
+        match (err.kind, err.code) {
+            (RecordedErrorKind::Response, Some(code)) => RpcError::RpcResponseError {
+                code,
+                message: err.message,
+                data: RpcResponseErrorData::Empty,
+            }
+            .into(),
+            // A `reqwest::Error` can't be built, an io error classifies the
+            // same.
+            (RecordedErrorKind::Transport, _) => io::Error::other(err.message).into(),
+            _ => RpcError::RpcRequestError(err.message).into(),
+        }
+    }
+}
+
+/// Forwards requests to `inner` and appends every request and its response
+/// to a cassette.
+pub struct RecordingSender<S = CustomHttpSender> {
+    inner: S,
+    cassette: Mutex<File>,
+}
+
+impl<S: RpcSender> RecordingSender<S> {
+    /// Records into `path`, replacing any previous cassette.
+    pub fn create<P: AsRef<Path>>(inner: S, path: P) -> io::Result<Self> {
+        Ok(Self {
+            inner,
+            cassette: Mutex::new(File::create(path)?),
+        })
+    }
+
+    fn record(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+        result: &client_error::Result<serde_json::Value>,
+    ) {
+        let interaction = Interaction::new(request, params, result);
+        let mut line = serde_json::to_string(&interaction).expect("serialize interaction");
+        line.push('\n');
+
+        let mut cassette = self.cassette.lock().unwrap();
+        if let Err(err) = cassette.write_all(line.as_bytes()) {
+            log::warn!("failed to record {} interaction: {}", request, err);
+        }
+    }
+}
+
+#[async_trait]
+impl<S: RpcSender + Send + Sync> RpcSender for RecordingSender<S> {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> client_error::Result<serde_json::Value> {
+        let result = self.inner.send(request, params.clone()).await;
+        self.record(request, params, &result);
+        result
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.inner.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.inner.url()
+    }
+}
+
+#[async_trait]
+impl<S: RpcBatchSender> RpcBatchSender for RecordingSender<S> {
+    /// Batches are recorded as their individual requests.
+    async fn send_batch(&self, requests: Vec<BatchRequest>) -> client_error::Result<BatchResponse> {
+        let responses = self.inner.send_batch(requests.clone()).await?;
+        for ((request, params), result) in requests.into_iter().zip(responses.iter()) {
+            self.record(request, params, result);
+        }
+        Ok(responses)
+    }
+}
+
+/// Serves responses from a cassette. Identical requests are answered with
+/// their recorded responses in order, the last one being repeated once the
+/// others are used up. Unknown requests fail.
+pub struct ReplaySender {
+    interactions: Mutex<HashMap<(String, String), VecDeque<Interaction>>>,
+    url: String,
+    stats: RwLock<RpcTransportStats>,
+}
+
+impl ReplaySender {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut interactions = vec![];
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let interaction = serde_json::from_str::<Interaction>(&line)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            interactions.push(interaction);
+        }
+
+        let mut sender = Self::from_interactions(interactions);
+        sender.url = format!("replay://{}", path.display());
+        Ok(sender)
+    }
+
+    pub fn from_interactions<I: IntoIterator<Item = Interaction>>(interactions: I) -> Self {
+        let mut by_request: HashMap<_, VecDeque<_>> = HashMap::new();
+        for interaction in interactions {
+            by_request
+                .entry(interaction.key())
+                .or_default()
+                .push_back(interaction);
+        }
+
+        Self {
+            interactions: Mutex::new(by_request),
+            url: "replay://".to_string(),
+            stats: RwLock::new(RpcTransportStats::default()),
+        }
+    }
+}
+
+#[async_trait]
+impl RpcSender for ReplaySender {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> client_error::Result<serde_json::Value> {
+        self.stats.write().unwrap().request_count += 1;
+
+        let key = (request.to_string(), params.to_string());
+        let mut interactions = self.interactions.lock().unwrap();
+        let interaction = match interactions.get_mut(&key) {
+            Some(queue) if queue.len() > 1 => queue.pop_front(),
+            Some(queue) => queue.front().cloned(),
+            None => None,
+        };
+
+        let interaction = match interaction {
+            Some(interaction) => interaction,
+            None => {
+                return Err(RpcError::RpcRequestError(format!(
+                    "No recorded response for {} {}",
+                    key.0, key.1
+                ))
+                .into())
+            }
+        };
+
+        match interaction.error {
+            Some(err) => Err(err.into()),
+            None => Ok(interaction.result.unwrap_or_default()),
+        }
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.stats.read().unwrap().clone()
+    }
+
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+}
+
+impl RpcBatchSender for ReplaySender {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CrawlerError;
+    use crate::rpc::circuit_breaker::is_endpoint_failure;
+
+    #[tokio::test]
+    async fn replays_recorded_interactions_in_order() {
+        let slot = |slot: u64| Interaction {
+            method: "getSlot".into(),
+            params: serde_json::Value::Null,
+            result: Some(slot.into()),
+            error: None,
+        };
+        let unhealthy = Interaction {
+            method: "getHealth".into(),
+            params: serde_json::Value::Null,
+            result: None,
+            error: Some(RecordedError {
+                kind: RecordedErrorKind::Response,
+                code: Some(-32005),
+                message: "Node is unhealthy".into(),
+            }),
+        };
+        let refused = Interaction {
+            method: "getBlockHeight".into(),
+            params: serde_json::Value::Null,
+            result: None,
+            error: Some(RecordedError::from(&ClientError::from(io::Error::from(
+                io::ErrorKind::ConnectionRefused,
+            )))),
+        };
+        let sender = ReplaySender::from_interactions(vec![slot(10), slot(11), unhealthy, refused]);
+
+        let get_slot = || sender.send(RpcRequest::GetSlot, serde_json::Value::Null);
+        assert_eq!(get_slot().await.unwrap(), 10);
+        assert_eq!(get_slot().await.unwrap(), 11);
+        assert_eq!(get_slot().await.unwrap(), 11);
+
+        let err = sender
+            .send(RpcRequest::GetHealth, serde_json::Value::Null)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            RecordedError::from(&err),
+            RecordedError {
+                kind: RecordedErrorKind::Response,
+                code: Some(-32005),
+                message: "Node is unhealthy".into(),
+            }
+        );
+        assert!(is_endpoint_failure(&err));
+
+        // Transport failures replay as transport failures.
+        let err = sender
+            .send(RpcRequest::GetBlockHeight, serde_json::Value::Null)
+            .await
+            .unwrap_err();
+        assert!(is_endpoint_failure(&err));
+        assert!(matches!(
+            CrawlerError::from(err),
+            CrawlerError::RpcTransport(_)
+        ));
+
+        assert!(sender
+            .send(RpcRequest::GetVersion, serde_json::Value::Null)
+            .await
+            .is_err());
+        assert_eq!(sender.get_transport_stats().request_count, 6);
+    }
+}
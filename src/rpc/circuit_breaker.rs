@@ -0,0 +1,208 @@
+use serde::Serialize;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_custom_error,
+    rpc_request::RpcError,
+};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Requests flow normally.
+    Closed,
+    /// Requests fail fast until the open timeout elapses.
+    Open,
+    /// A `getHealth` probe decides whether to close the circuit again.
+    HalfOpen,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive failures after which the circuit opens.
+    pub failure_threshold: u32,
+    /// Time spent open before probing the endpoint.
+    pub open_timeout: Duration,
+    /// Timeout of the `getHealth` probe, which is never retried.
+    pub probe_timeout: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            open_timeout: Duration::from_secs(30),
+            probe_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// What the breaker lets a caller do.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Permit {
+    Allowed,
+    /// The caller has to probe the endpoint and report the outcome through
+    /// `record_probe`.
+    Probe,
+    Rejected,
+}
+
+struct Inner {
+    state: CircuitState,
+    consecutive_failures: u32,
+    /// When the circuit opened, or when the running probe started.
+    since: Instant,
+}
+
+pub struct CircuitBreaker {
+    url: String,
+    config: CircuitBreakerConfig,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new<U: ToString>(url: U, config: CircuitBreakerConfig) -> Self {
+        Self {
+            url: url.to_string(),
+            config,
+            inner: Mutex::new(Inner {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                since: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.inner.lock().unwrap().state
+    }
+
+    pub fn config(&self) -> CircuitBreakerConfig {
+        self.config
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.inner.lock().unwrap().consecutive_failures
+    }
+
+    pub(crate) fn acquire(&self, now: Instant) -> Permit {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.state {
+            CircuitState::Closed => Permit::Allowed,
+            // A probe that outlives the open timeout was most likely
+            // cancelled, so another caller takes over.
+            CircuitState::Open | CircuitState::HalfOpen
+                if now.saturating_duration_since(inner.since) >= self.config.open_timeout =>
+            {
+                if inner.state == CircuitState::Open {
+                    log::info!("circuit breaker: {} half-open, probing", self.url);
+                }
+                inner.state = CircuitState::HalfOpen;
+                inner.since = now;
+                Permit::Probe
+            }
+            CircuitState::Open | CircuitState::HalfOpen => Permit::Rejected,
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures = 0;
+    }
+
+    pub fn record_failure(&self, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.consecutive_failures += 1;
+        if inner.state == CircuitState::Closed
+            && inner.consecutive_failures >= self.config.failure_threshold
+        {
+            log::warn!(
+                "circuit breaker: {} open after {} consecutive failures",
+                self.url,
+                inner.consecutive_failures
+            );
+            inner.state = CircuitState::Open;
+            inner.since = now;
+        }
+    }
+
+    pub(crate) fn record_probe(&self, healthy: bool, now: Instant) {
+        let mut inner = self.inner.lock().unwrap();
+        if healthy {
+            log::info!("circuit breaker: {} closed", self.url);
+            inner.state = CircuitState::Closed;
+            inner.consecutive_failures = 0;
+        } else {
+            log::warn!("circuit breaker: {} still unhealthy, reopening", self.url);
+            inner.state = CircuitState::Open;
+            inner.consecutive_failures += 1;
+        }
+        inner.since = now;
+    }
+
+    /// Error returned to callers while the circuit is not closed.
+    pub(crate) fn open_error(&self) -> ClientError {
+        std::io::Error::new(
+            std::io::ErrorKind::NotConnected,
+            format!("circuit breaker open for {}", self.url),
+        )
+        .into()
+    }
+}
+
+/// Errors that say something about the endpoint rather than the request:
+/// transport errors and `NodeUnhealthy` responses.
+pub fn is_endpoint_failure(err: &ClientError) -> bool {
+    match err.kind() {
+        ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_) => true,
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => {
+            *code == rpc_custom_error::JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opens_probes_and_closes() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 2,
+            open_timeout: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let breaker = CircuitBreaker::new("http://a", config);
+        let start = Instant::now();
+
+        breaker.record_failure(start);
+        breaker.record_success();
+        breaker.record_failure(start);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(breaker.acquire(start), Permit::Allowed);
+
+        breaker.record_failure(start);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.acquire(start), Permit::Rejected);
+
+        let later = start + Duration::from_secs(10);
+        assert_eq!(breaker.acquire(later), Permit::Probe);
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_eq!(breaker.acquire(later), Permit::Rejected);
+
+        breaker.record_probe(false, later);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.acquire(later), Permit::Rejected);
+
+        let much_later = later + Duration::from_secs(10);
+        assert_eq!(breaker.acquire(much_later), Permit::Probe);
+        breaker.record_probe(true, much_later);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(breaker.consecutive_failures(), 0);
+        assert!(is_endpoint_failure(&breaker.open_error()));
+    }
+}
@@ -1,11 +1,51 @@
-use std::{ops::Deref, time::Duration};
+use std::{ops::Deref, sync::Arc, time::Duration};
 
-use solana_client::rpc_client::{RpcClient, RpcClientConfig};
+use async_trait::async_trait;
+use solana_client::client_error;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_client::rpc_client::RpcClientConfig;
+use solana_client::rpc_request::RpcRequest;
+use solana_client::rpc_sender::{RpcSender, RpcTransportStats};
 use solana_sdk::commitment_config::CommitmentConfig;
 
+use super::batch::{BatchRequest, BatchResponse, RpcBatchSender};
 use super::custom_http_sender::CustomHttpSender;
+use super::pool::PooledHttpSender;
 
-pub struct SolanaClient(RpcClient);
+pub struct SolanaClient {
+    client: RpcClient,
+    sender: SharedSender,
+}
+
+/// Lets `RpcClient` and `SolanaClient::sender` use the same transport.
+#[derive(Clone)]
+struct SharedSender(Arc<dyn RpcBatchSender>);
+
+#[async_trait]
+impl RpcSender for SharedSender {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> client_error::Result<serde_json::Value> {
+        self.0.send(request, params).await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.0.get_transport_stats()
+    }
+
+    fn url(&self) -> String {
+        self.0.url()
+    }
+}
+
+#[async_trait]
+impl RpcBatchSender for SharedSender {
+    async fn send_batch(&self, requests: Vec<BatchRequest>) -> client_error::Result<BatchResponse> {
+        self.0.send_batch(requests).await
+    }
+}
 
 /// # Explorer - origin: https://explorer.solana.com
 /// https://explorer-api.mainnet-beta.solana.com/
@@ -20,22 +60,37 @@ pub struct SolanaClient(RpcClient);
 ///
 /// # SolanaFM - origin: https://solana.fm
 /// https://qn.solana.fm/
-/// 
+///
 /// # Cryptostarps - origin: https://cryptostraps.tools
 /// https://alice.genesysgo.net/
 /// https://pentacle.genesysgo.net/
 impl SolanaClient {
     pub fn new<U: ToString>(url: U) -> Self {
+        Self::new_sender(CustomHttpSender::new(url))
+    }
+
+    /// Client over a `PooledHttpSender` that fails over between `urls`.
+    pub fn new_pool<U: ToString>(urls: &[U]) -> Self {
+        Self::new_sender(PooledHttpSender::new(urls))
+    }
+
+    pub fn new_sender<S: RpcBatchSender + 'static>(sender: S) -> Self {
         let timeout = Duration::from_secs(45);
         let config = RpcClientConfig {
             commitment_config: CommitmentConfig::confirmed(),
             confirm_transaction_initial_timeout: Some(timeout),
         };
 
-        let sender = CustomHttpSender::new(url);
-        let client = RpcClient::new_sender(sender, config);
+        let sender = SharedSender(Arc::new(sender));
+        let client = RpcClient::new_sender(sender.clone(), config);
+
+        SolanaClient { client, sender }
+    }
 
-        SolanaClient(client)
+    /// Raw transport, for methods `RpcClient` doesn't know about such as the
+    /// Digital Asset Standard (DAS) api, and for batched requests.
+    pub fn sender(&self) -> &dyn RpcBatchSender {
+        self.sender.0.as_ref()
     }
 }
 
@@ -43,6 +98,6 @@ impl Deref for SolanaClient {
     type Target = RpcClient;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.client
     }
 }
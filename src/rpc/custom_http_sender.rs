@@ -1,7 +1,8 @@
-use actix_web::http::StatusCode;
 use async_trait::async_trait;
 use reqwest::header::{self, CONTENT_TYPE, RETRY_AFTER};
 use serde::Deserialize;
+
+use crate::error::{CrawlerError, Result};
 use solana_client::{
     client_error, rpc_custom_error,
     rpc_request::{RpcError, RpcRequest, RpcResponseErrorData},
@@ -9,6 +10,7 @@ use solana_client::{
     rpc_sender::*,
 };
 use std::{
+    collections::HashMap,
     sync::{
         atomic::{AtomicU64, Ordering},
         Arc, RwLock,
@@ -17,11 +19,26 @@ use std::{
 };
 use tokio::time::sleep;
 
+use super::batch::{BatchRequest, BatchResponse, RpcBatchSender};
+use super::circuit_breaker::{
+    is_endpoint_failure, CircuitBreaker, CircuitBreakerConfig, CircuitState, Permit,
+};
+use super::rate_limit::{RateLimit, TokenBucket};
+use super::retry::RetryPolicy;
+use super::single_flight::SingleFlight;
+
+#[derive(Clone)]
 pub struct CustomHttpSender {
     client: Arc<reqwest::Client>,
     url: String,
-    request_id: AtomicU64,
-    stats: RwLock<RpcTransportStats>,
+    /// `url` with the path token, if any. Kept out of logs and `url()`.
+    request_url: String,
+    request_id: Arc<AtomicU64>,
+    stats: Arc<RwLock<SenderStats>>,
+    retry_policy: Arc<RetryPolicy>,
+    rate_limiter: Option<Arc<TokenBucket>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    single_flight: Option<SingleFlight>,
 }
 
 impl CustomHttpSender {
@@ -30,31 +47,356 @@ impl CustomHttpSender {
     }
 
     pub fn new_with_timeout<U: ToString>(url: U, timeout: Duration) -> Self {
-        let mut default_headers = header::HeaderMap::new();
-        default_headers.append(
-            header::HeaderName::from_static("solana-e"),
-            header::HeaderValue::from_str("rust/1.0").unwrap(),
-        );
-        default_headers.append(
-            header::ORIGIN,
-            header::HeaderValue::from_str("https://magiceden.io").unwrap(),
-        );
+        Self::new_with_retry_policy(url, timeout, RetryPolicy::default())
+    }
 
-        let client = Arc::new(
-            reqwest::Client::builder()
-                .default_headers(default_headers)
-                .timeout(timeout)
-                .pool_idle_timeout(timeout)
-                .build()
-                .expect("build rpc client"),
-        );
+    /// Sender without custom headers or authentication. Use `builder` for
+    /// endpoints that need them, such as those listed on `SolanaClient`
+    /// which expect an `Origin`.
+    pub fn new_with_retry_policy<U: ToString>(
+        url: U,
+        timeout: Duration,
+        retry_policy: RetryPolicy,
+    ) -> Self {
+        Self::builder(url)
+            .timeout(timeout)
+            .retry_policy(retry_policy)
+            .build()
+            .expect("build rpc client")
+    }
+
+    pub fn builder<U: ToString>(url: U) -> CustomHttpSenderBuilder {
+        CustomHttpSenderBuilder::new(url)
+    }
+
+    /// Throttles requests, retries included, through the token bucket shared
+    /// by all senders of this url. Fails if they use a different limit.
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Result<Self> {
+        self.rate_limiter = Some(TokenBucket::for_url(&self.url, limit)?);
+        Ok(self)
+    }
+
+    /// Fails fast once the endpoint keeps failing, until a `getHealth` probe
+    /// succeeds. The breaker is shared by clones of this sender.
+    pub fn with_circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = Some(Arc::new(CircuitBreaker::new(&self.url, config)));
+        self
+    }
+
+    pub fn stats(&self) -> SenderStats {
+        self.stats.read().unwrap().clone()
+    }
+
+    pub fn retry_policy(&self) -> &RetryPolicy {
+        &self.retry_policy
+    }
+
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.circuit_breaker.as_ref().map(|x| x.state())
+    }
+
+    /// Lets the request through unless the circuit breaker is open. When it
+    /// is time to probe, the probe is sent first.
+    async fn check_circuit(&self) -> client_error::Result<()> {
+        let breaker = match &self.circuit_breaker {
+            Some(breaker) => breaker,
+            None => return Ok(()),
+        };
+
+        match breaker.acquire(Instant::now()) {
+            Permit::Allowed => Ok(()),
+            Permit::Rejected => Err(breaker.open_error()),
+            Permit::Probe => {
+                let request_id = self.request_id.fetch_add(1, Ordering::Relaxed);
+                let probe =
+                    build_request_json(RpcRequest::GetHealth, request_id, serde_json::Value::Null);
+                // Sent once: the endpoint is already known to be failing, so
+                // the caller shouldn't wait out retries and backoff.
+                let healthy = self
+                    .post_with(
+                        probe.to_string(),
+                        &RetryPolicy::none(),
+                        Some(breaker.config().probe_timeout),
+                    )
+                    .await
+                    .and_then(parse_response)
+                    .is_ok();
+
+                breaker.record_probe(healthy, Instant::now());
+                if healthy {
+                    Ok(())
+                } else {
+                    Err(breaker.open_error())
+                }
+            }
+        }
+    }
+
+    async fn send_once(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> client_error::Result<serde_json::Value> {
+        let request_id = self.request_id.fetch_add(1, Ordering::Relaxed);
+        let request_json = build_request_json(request, request_id, params).to_string();
+
+        self.check_circuit().await?;
+        let result = self.post(request_json).await.and_then(parse_response);
+        self.record_outcome(&result);
+        result
+    }
+
+    fn record_outcome<T>(&self, result: &client_error::Result<T>) {
+        if let Some(breaker) = &self.circuit_breaker {
+            match result {
+                Err(err) if is_endpoint_failure(err) => breaker.record_failure(Instant::now()),
+                _ => breaker.record_success(),
+            }
+        }
+    }
+}
+
+/// How requests are authenticated with a paid RPC provider.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RpcAuth {
+    /// `Authorization: Bearer <token>`.
+    Bearer(String),
+    /// Key sent in `header`, `x-api-key` by default.
+    ApiKey {
+        key: String,
+        #[serde(default = "default_api_key_header")]
+        header: String,
+    },
+    /// Token appended to the url path, as in `https://host/<token>/`.
+    PathToken(String),
+}
+
+fn default_api_key_header() -> String {
+    "x-api-key".to_string()
+}
 
+/// Settings of one RPC endpoint, as found in configuration files.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpSenderConfig {
+    pub url: String,
+    pub timeout_secs: Option<u64>,
+    pub headers: HashMap<String, String>,
+    pub auth: Option<RpcAuth>,
+    pub user_agent: Option<String>,
+    pub proxy: Option<String>,
+    pub gzip: Option<bool>,
+    pub brotli: Option<bool>,
+    pub rate_limit: Option<RateLimit>,
+}
+
+pub struct CustomHttpSenderBuilder {
+    url: String,
+    timeout: Duration,
+    headers: Vec<(String, String)>,
+    auth: Option<RpcAuth>,
+    user_agent: String,
+    proxy: Option<String>,
+    gzip: bool,
+    brotli: bool,
+    retry_policy: RetryPolicy,
+    rate_limit: Option<RateLimit>,
+    circuit_breaker: Option<CircuitBreakerConfig>,
+    single_flight: bool,
+}
+
+impl CustomHttpSenderBuilder {
+    pub fn new<U: ToString>(url: U) -> Self {
         Self {
-            client,
             url: url.to_string(),
-            request_id: AtomicU64::new(0),
-            stats: RwLock::new(RpcTransportStats::default()),
+            timeout: Duration::from_secs(30),
+            headers: vec![],
+            auth: None,
+            user_agent: concat!("solana-e/", env!("CARGO_PKG_VERSION")).to_string(),
+            proxy: None,
+            gzip: true,
+            brotli: true,
+            retry_policy: RetryPolicy::default(),
+            rate_limit: None,
+            circuit_breaker: None,
+            single_flight: true,
+        }
+    }
+
+    pub fn from_config(config: &HttpSenderConfig) -> Self {
+        let mut builder = Self::new(&config.url);
+        if let Some(timeout_secs) = config.timeout_secs {
+            builder = builder.timeout(Duration::from_secs(timeout_secs));
+        }
+        for (name, value) in config.headers.iter() {
+            builder = builder.header(name, value);
+        }
+        if let Some(auth) = config.auth.clone() {
+            builder = builder.auth(auth);
+        }
+        if let Some(user_agent) = &config.user_agent {
+            builder = builder.user_agent(user_agent);
         }
+        if let Some(proxy) = &config.proxy {
+            builder = builder.proxy(proxy);
+        }
+        if let Some(gzip) = config.gzip {
+            builder = builder.gzip(gzip);
+        }
+        if let Some(brotli) = config.brotli {
+            builder = builder.brotli(brotli);
+        }
+        if let Some(rate_limit) = config.rate_limit {
+            builder = builder.rate_limit(rate_limit);
+        }
+        builder
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Adds a header sent with every request.
+    pub fn header<K: ToString, V: ToString>(mut self, name: K, value: V) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn auth(mut self, auth: RpcAuth) -> Self {
+        self.auth = Some(auth);
+        self
+    }
+
+    pub fn bearer_auth<T: ToString>(self, token: T) -> Self {
+        self.auth(RpcAuth::Bearer(token.to_string()))
+    }
+
+    pub fn api_key<K: ToString>(self, key: K) -> Self {
+        self.auth(RpcAuth::ApiKey {
+            key: key.to_string(),
+            header: default_api_key_header(),
+        })
+    }
+
+    pub fn user_agent<U: ToString>(mut self, user_agent: U) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Routes every request through `proxy`, e.g. `http://localhost:3128`.
+    pub fn proxy<P: ToString>(mut self, proxy: P) -> Self {
+        self.proxy = Some(proxy.to_string());
+        self
+    }
+
+    pub fn gzip(mut self, enable: bool) -> Self {
+        self.gzip = enable;
+        self
+    }
+
+    pub fn brotli(mut self, enable: bool) -> Self {
+        self.brotli = enable;
+        self
+    }
+
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    pub fn circuit_breaker(mut self, config: CircuitBreakerConfig) -> Self {
+        self.circuit_breaker = Some(config);
+        self
+    }
+
+    /// Whether concurrent identical calls share one request, on by default.
+    pub fn single_flight(mut self, enable: bool) -> Self {
+        self.single_flight = enable;
+        self
+    }
+
+    pub fn build(self) -> Result<CustomHttpSender> {
+        let invalid = |what: &str, err: &dyn std::fmt::Display| {
+            CrawlerError::InvalidConfig(format!("{} for rpc endpoint {}: {}", what, self.url, err))
+        };
+
+        let mut request_url =
+            reqwest::Url::parse(&self.url).map_err(|err| invalid("invalid url", &err))?;
+
+        let mut default_headers = header::HeaderMap::new();
+        for (name, value) in self.headers.iter() {
+            let name = header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|err| invalid("invalid header name", &err))?;
+            let value = header::HeaderValue::from_str(value)
+                .map_err(|err| invalid("invalid header value", &err))?;
+            default_headers.append(name, value);
+        }
+
+        let secret = match &self.auth {
+            Some(RpcAuth::Bearer(token)) => {
+                Some((header::AUTHORIZATION.as_str(), format!("Bearer {}", token)))
+            }
+            Some(RpcAuth::ApiKey { key, header }) => Some((header.as_str(), key.clone())),
+            Some(RpcAuth::PathToken(token)) => {
+                request_url
+                    .path_segments_mut()
+                    .map_err(|_| invalid("invalid url", &"cannot append path token"))?
+                    .pop_if_empty()
+                    .push(token)
+                    .push("");
+                None
+            }
+            None => None,
+        };
+        if let Some((name, value)) = secret {
+            let name = header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|err| invalid("invalid auth header", &err))?;
+            let mut value = header::HeaderValue::from_str(&value)
+                .map_err(|err| invalid("invalid auth value", &err))?;
+            value.set_sensitive(true);
+            default_headers.insert(name, value);
+        }
+
+        let mut client = reqwest::Client::builder()
+            .default_headers(default_headers)
+            .user_agent(&self.user_agent)
+            .timeout(self.timeout)
+            .pool_idle_timeout(self.timeout)
+            .gzip(self.gzip)
+            .brotli(self.brotli);
+        if let Some(proxy) = &self.proxy {
+            let proxy = reqwest::Proxy::all(proxy).map_err(|err| invalid("invalid proxy", &err))?;
+            client = client.proxy(proxy);
+        }
+        let client = client
+            .build()
+            .map_err(|err| invalid("failed to build http client", &err))?;
+
+        let mut sender = CustomHttpSender {
+            client: Arc::new(client),
+            url: self.url.clone(),
+            request_url: request_url.to_string(),
+            request_id: Arc::new(AtomicU64::new(0)),
+            stats: Arc::new(RwLock::new(SenderStats::default())),
+            retry_policy: Arc::new(self.retry_policy),
+            rate_limiter: None,
+            circuit_breaker: None,
+            single_flight: self.single_flight.then(SingleFlight::new),
+        };
+        if let Some(limit) = self.rate_limit {
+            sender = sender.with_rate_limit(limit)?;
+        }
+        if let Some(config) = self.circuit_breaker {
+            sender = sender.with_circuit_breaker(config);
+        }
+        Ok(sender)
     }
 }
 
@@ -63,32 +405,60 @@ pub struct RpcErrorObject {
     pub code: i64,
     pub message: String,
 }
+/// Transport stats of a sender. `transport.rate_limited_time` only counts
+/// waits for the token bucket, backoff before retries is kept apart.
+#[derive(Default, Clone)]
+pub struct SenderStats {
+    pub transport: RpcTransportStats,
+    pub retry_backoff_time: Duration,
+}
+
+impl SenderStats {
+    pub fn add(&mut self, other: &SenderStats) {
+        self.transport.request_count += other.transport.request_count;
+        self.transport.elapsed_time += other.transport.elapsed_time;
+        self.transport.rate_limited_time += other.transport.rate_limited_time;
+        self.retry_backoff_time += other.retry_backoff_time;
+    }
+}
+
 struct StatsUpdater<'a> {
-    stats: &'a RwLock<RpcTransportStats>,
+    stats: &'a RwLock<SenderStats>,
     request_start_time: Instant,
     rate_limited_time: Duration,
+    retry_backoff_time: Duration,
+    retries: u64,
 }
 
 impl<'a> StatsUpdater<'a> {
-    fn new(stats: &'a RwLock<RpcTransportStats>) -> Self {
+    fn new(stats: &'a RwLock<SenderStats>) -> Self {
         Self {
             stats,
             request_start_time: Instant::now(),
             rate_limited_time: Duration::default(),
+            retry_backoff_time: Duration::default(),
+            retries: 0,
         }
     }
 
     fn add_rate_limited_time(&mut self, duration: Duration) {
         self.rate_limited_time += duration;
     }
+
+    /// Records a retry; every attempt counts as a request.
+    fn add_retry(&mut self, backoff: Duration) {
+        self.retries += 1;
+        self.retry_backoff_time += backoff;
+    }
 }
 
 impl<'a> Drop for StatsUpdater<'a> {
     fn drop(&mut self) {
         let mut stats = self.stats.write().unwrap();
-        stats.request_count += 1;
-        stats.elapsed_time += Instant::now().duration_since(self.request_start_time);
-        stats.rate_limited_time += self.rate_limited_time;
+        stats.transport.request_count += 1 + self.retries as usize;
+        stats.transport.elapsed_time += Instant::now().duration_since(self.request_start_time);
+        stats.transport.rate_limited_time += self.rate_limited_time;
+        stats.retry_backoff_time += self.retry_backoff_time;
     }
 }
 
@@ -102,108 +472,408 @@ fn build_request_json(req: RpcRequest, id: u64, params: serde_json::Value) -> se
     })
 }
 
-#[async_trait]
-impl RpcSender for CustomHttpSender {
-    async fn send(
+impl CustomHttpSender {
+    /// Posts `body`, retrying per the retry policy, and returns the decoded
+    /// JSON response.
+    async fn post(&self, body: String) -> client_error::Result<serde_json::Value> {
+        self.post_with(body, &self.retry_policy, None).await
+    }
+
+    /// Posts `body` retrying as `retry_policy` says, with `timeout` replacing
+    /// the client's if set.
+    async fn post_with(
         &self,
-        request: RpcRequest,
-        params: serde_json::Value,
+        body: String,
+        retry_policy: &RetryPolicy,
+        timeout: Option<Duration>,
     ) -> client_error::Result<serde_json::Value> {
         let mut stats_updater = StatsUpdater::new(&self.stats);
 
-        let request_id = self.request_id.fetch_add(1, Ordering::Relaxed);
-        let request_json = build_request_json(request, request_id, params).to_string();
-
-        let mut too_many_requests_retries = 5;
+        let mut attempt = 0;
         loop {
+            attempt += 1;
+            if let Some(rate_limiter) = &self.rate_limiter {
+                let waited = rate_limiter.acquire().await;
+                stats_updater.add_rate_limited_time(waited);
+            }
+
             let response = {
                 let client = self.client.clone();
-                let request_json = request_json.clone();
-                client
-                    .post(&self.url)
+                let body = body.clone();
+                let mut request = client
+                    .post(&self.request_url)
                     .header(CONTENT_TYPE, "application/json")
-                    .body(request_json)
-                    .send()
-                    .await
-            }?;
+                    .body(body);
+                if let Some(timeout) = timeout {
+                    request = request.timeout(timeout);
+                }
+                request.send().await
+            };
 
-            if !response.status().is_success() {
-                if response.status() == StatusCode::TOO_MANY_REQUESTS
-                    && too_many_requests_retries > 0
-                {
-                    let mut duration = Duration::from_millis(500);
-                    if let Some(retry_after) = response.headers().get(RETRY_AFTER) {
-                        if let Ok(retry_after) = retry_after.to_str() {
-                            if let Ok(retry_after) = retry_after.parse::<u64>() {
-                                if retry_after < 120 {
-                                    duration = Duration::from_secs(retry_after);
-                                }
-                            }
+            let response = match response {
+                Ok(response) => response,
+                Err(err) => {
+                    let elapsed = stats_updater.request_start_time.elapsed();
+                    match retry_policy.backoff_for_error(attempt, elapsed, &err) {
+                        Some(duration) => {
+                            log::debug!(
+                                "Request failed: {}, attempt {}, pausing for {:?}",
+                                err,
+                                attempt,
+                                duration
+                            );
+                            sleep(duration).await;
+                            stats_updater.add_retry(duration);
+                            continue;
                         }
+                        None => return Err(err.into()),
                     }
+                }
+            };
 
-                    too_many_requests_retries -= 1;
+            if !response.status().is_success() {
+                let status = response.status();
+                let retry_after = response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|x| x.to_str().ok())
+                    .and_then(|x| x.parse::<u64>().ok())
+                    .map(Duration::from_secs);
+                let elapsed = stats_updater.request_start_time.elapsed();
+                let backoff =
+                    retry_policy.backoff_for_status(attempt, elapsed, status, retry_after);
+                if let Some(duration) = backoff {
                     log::debug!(
-                        "Too many requests: server responded with {:?}, {} retries left, pausing for {:?}",
-                        response, too_many_requests_retries, duration
+                        "Server responded with {:?}, attempt {}, pausing for {:?}",
+                        response,
+                        attempt,
+                        duration
                     );
 
                     sleep(duration).await;
-                    stats_updater.add_rate_limited_time(duration);
+                    stats_updater.add_retry(duration);
                     continue;
                 }
                 return Err(response.error_for_status().unwrap_err().into());
             }
 
-            let mut json = response.json::<serde_json::Value>().await?;
-            if json["error"].is_object() {
-                return match serde_json::from_value::<RpcErrorObject>(json["error"].clone()) {
-                    Ok(rpc_error_object) => {
-                        let data = match rpc_error_object.code {
-                                    rpc_custom_error::JSON_RPC_SERVER_ERROR_SEND_TRANSACTION_PREFLIGHT_FAILURE => {
-                                        match serde_json::from_value::<RpcSimulateTransactionResult>(json["error"]["data"].clone()) {
-                                            Ok(data) => RpcResponseErrorData::SendTransactionPreflightFailure(data),
-                                            Err(err) => {
-                                                log::debug!("Failed to deserialize RpcSimulateTransactionResult: {:?}", err);
-                                                RpcResponseErrorData::Empty
-                                            }
-                                        }
-                                    },
-                                    rpc_custom_error::JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY => {
-                                        match serde_json::from_value::<rpc_custom_error::NodeUnhealthyErrorData>(json["error"]["data"].clone()) {
-                                            Ok(rpc_custom_error::NodeUnhealthyErrorData {num_slots_behind}) => RpcResponseErrorData::NodeUnhealthy {num_slots_behind},
-                                            Err(_err) => {
-                                                RpcResponseErrorData::Empty
-                                            }
-                                        }
-                                    },
-                                    _ => RpcResponseErrorData::Empty
-                                };
-
-                        Err(RpcError::RpcResponseError {
-                            code: rpc_error_object.code,
-                            message: rpc_error_object.message,
-                            data,
+            let json = match response.json::<serde_json::Value>().await {
+                Ok(json) => json,
+                Err(err) => {
+                    let elapsed = stats_updater.request_start_time.elapsed();
+                    match retry_policy.backoff_for_error(attempt, elapsed, &err) {
+                        Some(duration) => {
+                            log::debug!(
+                                "Failed to read response: {}, attempt {}, pausing for {:?}",
+                                err,
+                                attempt,
+                                duration
+                            );
+                            sleep(duration).await;
+                            stats_updater.add_retry(duration);
+                            continue;
+                        }
+                        None => return Err(err.into()),
+                    }
+                }
+            };
+            return Ok(json);
+        }
+    }
+}
+
+/// Extracts the result of a single JSON-RPC response, decoding its error
+/// object if any.
+#[allow(clippy::result_large_err)] // same error type as `RpcSender::send`
+fn parse_response(mut json: serde_json::Value) -> client_error::Result<serde_json::Value> {
+    if json["error"].is_object() {
+        return match serde_json::from_value::<RpcErrorObject>(json["error"].clone()) {
+            Ok(rpc_error_object) => {
+                let data = match rpc_error_object.code {
+                    rpc_custom_error::JSON_RPC_SERVER_ERROR_SEND_TRANSACTION_PREFLIGHT_FAILURE => {
+                        match serde_json::from_value::<RpcSimulateTransactionResult>(
+                            json["error"]["data"].clone(),
+                        ) {
+                            Ok(data) => RpcResponseErrorData::SendTransactionPreflightFailure(data),
+                            Err(err) => {
+                                log::debug!(
+                                    "Failed to deserialize RpcSimulateTransactionResult: {:?}",
+                                    err
+                                );
+                                RpcResponseErrorData::Empty
+                            }
+                        }
+                    }
+                    rpc_custom_error::JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY => {
+                        match serde_json::from_value::<rpc_custom_error::NodeUnhealthyErrorData>(
+                            json["error"]["data"].clone(),
+                        ) {
+                            Ok(rpc_custom_error::NodeUnhealthyErrorData { num_slots_behind }) => {
+                                RpcResponseErrorData::NodeUnhealthy { num_slots_behind }
+                            }
+                            Err(_err) => RpcResponseErrorData::Empty,
                         }
-                        .into())
                     }
-                    Err(err) => Err(RpcError::RpcRequestError(format!(
-                        "Failed to deserialize RPC error response: {} [{}]",
-                        serde_json::to_string(&json["error"]).unwrap(),
-                        err
-                    ))
-                    .into()),
+                    _ => RpcResponseErrorData::Empty,
                 };
+
+                Err(RpcError::RpcResponseError {
+                    code: rpc_error_object.code,
+                    message: rpc_error_object.message,
+                    data,
+                }
+                .into())
             }
-            return Ok(json["result"].take());
+            Err(err) => Err(RpcError::RpcRequestError(format!(
+                "Failed to deserialize RPC error response: {} [{}]",
+                serde_json::to_string(&json["error"]).unwrap(),
+                err
+            ))
+            .into()),
+        };
+    }
+    Ok(json["result"].take())
+}
+
+/// Requests whose identical concurrent calls may share one response. Each
+/// transaction submission has to reach the node.
+fn is_coalescable(request: RpcRequest) -> bool {
+    !matches!(
+        request,
+        RpcRequest::SendTransaction | RpcRequest::RequestAirdrop
+    )
+}
+
+/// Matches the items of a batch response to the request `ids` they answer.
+/// A response that is not an array is an error for the whole batch.
+#[allow(clippy::result_large_err)] // same error type as `RpcSender::send`
+fn parse_batch_response(
+    ids: &[u64],
+    json: serde_json::Value,
+) -> client_error::Result<BatchResponse> {
+    let items = match json {
+        serde_json::Value::Array(items) => items,
+        json => {
+            parse_response(json)?;
+            return Err(RpcError::RpcRequestError(
+                "Expected an array in response to a batch request".to_string(),
+            )
+            .into());
         }
+    };
+
+    let mut items_by_id: HashMap<u64, serde_json::Value> = items
+        .into_iter()
+        .filter_map(|item| Some((item["id"].as_u64()?, item)))
+        .collect();
+
+    let responses = ids
+        .iter()
+        .map(|id| match items_by_id.remove(id) {
+            Some(item) => parse_response(item),
+            None => Err(RpcError::RpcRequestError(format!(
+                "Missing response for batch request id {}",
+                id
+            ))
+            .into()),
+        })
+        .collect();
+
+    Ok(responses)
+}
+
+#[async_trait]
+impl RpcSender for CustomHttpSender {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> client_error::Result<serde_json::Value> {
+        let single_flight = match &self.single_flight {
+            Some(single_flight) if is_coalescable(request) => single_flight,
+            _ => return self.send_once(request, params).await,
+        };
+
+        let sender = self.clone();
+        let call_params = params.clone();
+        let call = async move { sender.send_once(request, call_params).await };
+        single_flight.run(request.to_string(), &params, call).await
     }
 
     fn get_transport_stats(&self) -> RpcTransportStats {
-        self.stats.read().unwrap().clone()
+        self.stats.read().unwrap().transport.clone()
     }
 
     fn url(&self) -> String {
         self.url.clone()
     }
 }
+
+#[async_trait]
+impl RpcBatchSender for CustomHttpSender {
+    /// Packs `requests` into a single JSON-RPC batch POST.
+    async fn send_batch(&self, requests: Vec<BatchRequest>) -> client_error::Result<BatchResponse> {
+        if requests.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut ids = Vec::with_capacity(requests.len());
+        let batch: Vec<serde_json::Value> = requests
+            .into_iter()
+            .map(|(request, params)| {
+                let request_id = self.request_id.fetch_add(1, Ordering::Relaxed);
+                ids.push(request_id);
+                build_request_json(request, request_id, params)
+            })
+            .collect();
+
+        self.check_circuit().await?;
+        let result = self.post(serde_json::Value::Array(batch).to_string()).await;
+        self.record_outcome(&result);
+        parse_batch_response(&ids, result?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::test_util::refused_url;
+    use solana_client::client_error::ClientErrorKind;
+
+    #[test]
+    fn builds_authenticated_senders() {
+        let sender = CustomHttpSender::builder("https://example.quiknode.pro")
+            .auth(RpcAuth::PathToken("secret".into()))
+            .build()
+            .unwrap();
+        assert_eq!(sender.url(), "https://example.quiknode.pro");
+        assert_eq!(sender.request_url, "https://example.quiknode.pro/secret/");
+
+        let config = serde_json::from_value::<HttpSenderConfig>(serde_json::json!({
+            "url": "https://rpc.example.com/",
+            "timeout_secs": 5,
+            "headers": { "x-client": "solana-e" },
+            "auth": { "api_key": { "key": "secret" } },
+            "proxy": "http://localhost:3128",
+            "rate_limit": { "requests_per_second": 10.0, "burst": 20 },
+        }))
+        .unwrap();
+        assert_eq!(
+            config.auth,
+            Some(RpcAuth::ApiKey {
+                key: "secret".into(),
+                header: "x-api-key".into(),
+            })
+        );
+        let sender = CustomHttpSenderBuilder::from_config(&config)
+            .build()
+            .unwrap();
+        assert_eq!(sender.request_url, "https://rpc.example.com/");
+
+        let invalid = CustomHttpSender::builder("https://rpc.example.com/")
+            .header("bad header", "value")
+            .build();
+        assert!(matches!(invalid, Err(CrawlerError::InvalidConfig(_))));
+        assert!(CustomHttpSender::builder("not a url").build().is_err());
+
+        // Limits are checked once, when they are built or deserialized.
+        let zero_rate = serde_json::from_value::<HttpSenderConfig>(serde_json::json!({
+            "url": "https://rpc.example.com/",
+            "rate_limit": { "requests_per_second": 0.0, "burst": 1 },
+        }));
+        assert!(zero_rate
+            .unwrap_err()
+            .to_string()
+            .contains("0 requests per second"));
+    }
+
+    #[tokio::test]
+    async fn probes_open_circuits_once() {
+        let sender = CustomHttpSender::builder(refused_url())
+            .circuit_breaker(CircuitBreakerConfig {
+                failure_threshold: 1,
+                open_timeout: Duration::ZERO,
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        assert!(sender.retry_policy().max_attempts > 1);
+        let breaker = sender.circuit_breaker.clone().unwrap();
+        breaker.record_failure(Instant::now());
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Open));
+
+        // The caller winning the probe isn't held through the retries.
+        let start = Instant::now();
+        let err = sender
+            .send(RpcRequest::GetSlot, serde_json::Value::Null)
+            .await
+            .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(is_endpoint_failure(&err));
+        assert_eq!(sender.get_transport_stats().request_count, 1);
+        assert_eq!(sender.circuit_state(), Some(CircuitState::Open));
+    }
+
+    #[tokio::test]
+    async fn keeps_retry_backoff_apart_from_rate_limiting() {
+        let sender = CustomHttpSender::new_with_retry_policy(
+            refused_url(),
+            Duration::from_secs(1),
+            RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(10),
+                jitter: 0.0,
+                ..Default::default()
+            },
+        );
+        assert!(sender
+            .send(RpcRequest::GetSlot, serde_json::Value::Null)
+            .await
+            .is_err());
+
+        let stats = sender.stats();
+        assert_eq!(stats.transport.request_count, 3);
+        assert_eq!(stats.retry_backoff_time, Duration::from_millis(30));
+        assert_eq!(stats.transport.rate_limited_time, Duration::ZERO);
+    }
+
+    #[test]
+    fn correlates_batch_responses_by_id() {
+        let json = serde_json::json!([
+            {"jsonrpc": "2.0", "id": 8, "result": 42},
+            {
+                "jsonrpc": "2.0",
+                "id": 7,
+                "error": {
+                    "code": -32005,
+                    "message": "Node is behind by 12 slots",
+                    "data": {"numSlotsBehind": 12},
+                },
+            },
+        ]);
+
+        let responses = parse_batch_response(&[7, 8, 9], json).unwrap();
+        assert_eq!(responses.len(), 3);
+        match responses[0].as_ref().unwrap_err().kind() {
+            ClientErrorKind::RpcError(RpcError::RpcResponseError { code, data, .. }) => {
+                assert_eq!(*code, -32005);
+                assert!(matches!(
+                    data,
+                    RpcResponseErrorData::NodeUnhealthy {
+                        num_slots_behind: Some(12)
+                    }
+                ));
+            }
+            kind => panic!("unexpected error {:?}", kind),
+        }
+        assert_eq!(responses[1].as_ref().unwrap(), &serde_json::json!(42));
+        assert!(responses[2].is_err());
+
+        let rejected = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": null,
+            "error": {"code": -32600, "message": "Invalid request"},
+        });
+        assert!(parse_batch_response(&[1], rejected).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Response types for the Digital Asset Standard (DAS) read api exposed by
+//! compression aware RPC providers.
+
+use serde::Deserialize;
+use solana_client::rpc_request::RpcRequest;
+
+pub const GET_ASSETS_BY_OWNER: RpcRequest = RpcRequest::Custom {
+    method: "getAssetsByOwner",
+};
+
+/// Largest page size accepted by `getAssetsByOwner`.
+pub const MAX_PAGE_LIMIT: u64 = 1000;
+
+#[derive(Deserialize, Debug)]
+pub struct AssetList {
+    pub total: u64,
+    pub limit: u64,
+    pub page: Option<u64>,
+    pub items: Vec<Asset>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Asset {
+    pub id: String,
+    pub interface: String,
+    pub content: Option<AssetContent>,
+    #[serde(default)]
+    pub authorities: Vec<AssetAuthority>,
+    pub compression: Option<AssetCompression>,
+    #[serde(default)]
+    pub grouping: Vec<AssetGroup>,
+    pub royalty: Option<AssetRoyalty>,
+    #[serde(default)]
+    pub creators: Vec<AssetCreator>,
+    pub supply: Option<AssetSupply>,
+    #[serde(default)]
+    pub mutable: bool,
+    #[serde(default)]
+    pub burnt: bool,
+}
+
+impl Asset {
+    pub fn is_compressed(&self) -> bool {
+        self.compression
+            .as_ref()
+            .map(|x| x.compressed)
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssetContent {
+    #[serde(default)]
+    pub json_uri: String,
+    pub metadata: Option<AssetMetadata>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssetMetadata {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub symbol: String,
+    pub token_standard: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssetAuthority {
+    pub address: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssetCompression {
+    pub compressed: bool,
+    #[serde(default)]
+    pub tree: String,
+    #[serde(default)]
+    pub leaf_id: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssetGroup {
+    pub group_key: String,
+    pub group_value: String,
+    /// Only reported by some providers; collections are verified when absent.
+    pub verified: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssetRoyalty {
+    pub basis_points: u16,
+    #[serde(default)]
+    pub primary_sale_happened: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssetCreator {
+    pub address: String,
+    pub share: u8,
+    #[serde(default)]
+    pub verified: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AssetSupply {
+    pub print_max_supply: Option<u64>,
+    pub print_current_supply: Option<u64>,
+    pub edition_nonce: Option<u8>,
+}
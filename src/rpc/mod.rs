@@ -1,2 +1,15 @@
+pub mod batch;
+pub mod cache;
+pub mod cassette;
+pub mod circuit_breaker;
 pub mod client;
 pub mod custom_http_sender;
+pub mod das;
+pub mod pool;
+pub mod pubsub;
+pub mod rate_limit;
+pub mod retry;
+pub mod single_flight;
+
+#[cfg(test)]
+mod test_util;
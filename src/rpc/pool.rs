@@ -0,0 +1,357 @@
+use async_trait::async_trait;
+use futures::future::join_all;
+use solana_client::{client_error, rpc_request::RpcRequest, rpc_sender::*};
+use std::{
+    future::Future,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use super::batch::{BatchRequest, BatchResponse, RpcBatchSender};
+use super::circuit_breaker::{is_endpoint_failure, CircuitBreakerConfig, CircuitState};
+use super::custom_http_sender::{CustomHttpSender, SenderStats};
+use super::retry::RetryPolicy;
+
+/// Weight of the newest sample in the latency and error rate averages.
+const EWMA_ALPHA: f64 = 0.2;
+
+/// Score penalty per slot an endpoint lags behind the most recent one.
+const SLOT_LAG_PENALTY_MS: f64 = 50.0;
+
+#[derive(Clone, Debug, Default)]
+pub struct EndpointHealth {
+    pub url: String,
+    /// Moving average of successful request latency.
+    pub latency: Duration,
+    /// Moving average of failed requests, between 0 and 1.
+    pub error_rate: f64,
+    pub successes: u64,
+    pub failures: u64,
+    /// Cleared on transport errors and `NodeUnhealthy` responses, restored
+    /// by the next success or health check.
+    pub healthy: bool,
+    pub slot: Option<u64>,
+    /// Slots behind the most advanced endpoint of the pool.
+    pub slot_lag: Option<u64>,
+    /// State of the endpoint's circuit breaker, if it has one.
+    pub circuit: Option<CircuitState>,
+}
+
+impl EndpointHealth {
+    /// Lower is better.
+    fn score(&self) -> f64 {
+        let latency = self.latency.as_secs_f64() * 1000.0;
+        let lag = self.slot_lag.unwrap_or(0) as f64 * SLOT_LAG_PENALTY_MS;
+        (latency + lag) * (1.0 + self.error_rate * 10.0)
+    }
+
+    fn record_success(&mut self, elapsed: Duration) {
+        self.successes += 1;
+        self.healthy = true;
+        self.error_rate *= 1.0 - EWMA_ALPHA;
+        self.latency = if self.successes == 1 {
+            elapsed
+        } else {
+            self.latency.mul_f64(1.0 - EWMA_ALPHA) + elapsed.mul_f64(EWMA_ALPHA)
+        };
+    }
+
+    fn record_failure(&mut self) {
+        self.failures += 1;
+        self.healthy = false;
+        self.error_rate = self.error_rate * (1.0 - EWMA_ALPHA) + EWMA_ALPHA;
+    }
+}
+
+struct Endpoint {
+    sender: CustomHttpSender,
+    health: RwLock<EndpointHealth>,
+}
+
+/// Spreads requests over several RPC endpoints, preferring the fastest
+/// healthy one and failing over to the next on transport errors or
+/// `NodeUnhealthy` responses.
+#[derive(Clone)]
+pub struct PooledHttpSender {
+    endpoints: Arc<Vec<Endpoint>>,
+}
+
+impl PooledHttpSender {
+    /// Pool of endpoints, each behind its own circuit breaker and retrying as
+    /// `endpoint_retry_policy` says.
+    pub fn new<U: ToString>(urls: &[U]) -> Self {
+        let retry_policy = Self::endpoint_retry_policy(urls.len());
+        Self::from_senders(
+            urls.iter()
+                .map(|url| {
+                    CustomHttpSender::new_with_retry_policy(
+                        url.to_string(),
+                        Duration::from_secs(30),
+                        retry_policy.clone(),
+                    )
+                    .with_circuit_breaker(CircuitBreakerConfig::default())
+                })
+                .collect(),
+        )
+    }
+
+    /// Retry policy for the senders of a pool of `endpoints`. Failing over
+    /// to the next endpoint beats waiting out the backoff of a failing one,
+    /// so endpoints only retry on their own when they are alone.
+    pub fn endpoint_retry_policy(endpoints: usize) -> RetryPolicy {
+        if endpoints > 1 {
+            RetryPolicy::none()
+        } else {
+            RetryPolicy::default()
+        }
+    }
+
+    pub fn from_senders(senders: Vec<CustomHttpSender>) -> Self {
+        assert!(!senders.is_empty(), "rpc pool needs at least one endpoint");
+
+        let endpoints = senders
+            .into_iter()
+            .map(|sender| Endpoint {
+                health: RwLock::new(EndpointHealth {
+                    url: sender.url(),
+                    healthy: true,
+                    ..Default::default()
+                }),
+                sender,
+            })
+            .collect();
+
+        Self {
+            endpoints: Arc::new(endpoints),
+        }
+    }
+
+    /// Stats of all endpoints added up.
+    pub fn stats(&self) -> SenderStats {
+        let mut stats = SenderStats::default();
+        for endpoint in self.endpoints.iter() {
+            stats.add(&endpoint.sender.stats());
+        }
+        stats
+    }
+
+    pub fn health(&self) -> Vec<EndpointHealth> {
+        self.endpoints
+            .iter()
+            .map(|x| EndpointHealth {
+                circuit: x.sender.circuit_state(),
+                ..x.health.read().unwrap().clone()
+            })
+            .collect()
+    }
+
+    /// Probes every endpoint with `getHealth` and `getSlot`, then updates
+    /// their slot lag relative to the most advanced one.
+    pub async fn check_health(&self) {
+        let probes = self.endpoints.iter().map(|endpoint| async move {
+            let start = Instant::now();
+            let health = endpoint
+                .sender
+                .send(RpcRequest::GetHealth, serde_json::Value::Null)
+                .await;
+            let slot = endpoint
+                .sender
+                .send(RpcRequest::GetSlot, serde_json::Value::Null)
+                .await;
+
+            let mut state = endpoint.health.write().unwrap();
+            match (health, slot) {
+                (Ok(_), Ok(slot)) => {
+                    state.record_success(start.elapsed());
+                    state.slot = slot.as_u64();
+                }
+                (health, slot) => {
+                    let err = health.err().or_else(|| slot.err());
+                    log::debug!("rpc pool: {} failed health check: {:?}", state.url, err);
+                    state.record_failure();
+                }
+            }
+        });
+        join_all(probes).await;
+
+        let max_slot = self
+            .endpoints
+            .iter()
+            .filter_map(|x| x.health.read().unwrap().slot)
+            .max();
+        for endpoint in self.endpoints.iter() {
+            let mut state = endpoint.health.write().unwrap();
+            state.slot_lag = match (max_slot, state.slot) {
+                (Some(max_slot), Some(slot)) => Some(max_slot.saturating_sub(slot)),
+                _ => None,
+            };
+        }
+    }
+
+    /// Runs `check_health` every `interval` until the returned handle is
+    /// aborted.
+    pub fn spawn_health_checks(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let pool = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                pool.check_health().await;
+            }
+        })
+    }
+
+    /// Runs `call` against endpoints in ranked order until one of them
+    /// returns something other than a fail-over error.
+    async fn with_failover<T, F, Fut>(&self, call: F) -> client_error::Result<T>
+    where
+        F: Fn(CustomHttpSender) -> Fut,
+        Fut: Future<Output = client_error::Result<T>>,
+    {
+        let mut last_error = None;
+        for index in self.ranked() {
+            let endpoint = &self.endpoints[index];
+            let start = Instant::now();
+            let result = call(endpoint.sender.clone()).await;
+
+            let mut health = endpoint.health.write().unwrap();
+            match result {
+                Err(err) if is_endpoint_failure(&err) => {
+                    health.record_failure();
+                    log::debug!("rpc pool: {} failed, trying next: {}", health.url, err);
+                    last_error = Some(err);
+                }
+                result => {
+                    health.record_success(start.elapsed());
+                    return result;
+                }
+            }
+        }
+
+        Err(last_error.expect("rpc pool has at least one endpoint"))
+    }
+
+    /// Endpoint indexes ordered from healthiest to least healthy, endpoints
+    /// with an open circuit last.
+    fn ranked(&self) -> Vec<usize> {
+        let health = self.health();
+        let mut ranked: Vec<usize> = (0..health.len()).collect();
+        ranked.sort_by(|a, b| {
+            let (a, b) = (&health[*a], &health[*b]);
+            let closed = |x: &EndpointHealth| x.circuit != Some(CircuitState::Open);
+            closed(b)
+                .cmp(&closed(a))
+                .then_with(|| b.healthy.cmp(&a.healthy))
+                .then_with(|| a.score().total_cmp(&b.score()))
+        });
+        ranked
+    }
+}
+
+#[async_trait]
+impl RpcSender for PooledHttpSender {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> client_error::Result<serde_json::Value> {
+        self.with_failover(|sender| {
+            let params = params.clone();
+            async move { sender.send(request, params).await }
+        })
+        .await
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.stats().transport
+    }
+
+    fn url(&self) -> String {
+        let index = self.ranked()[0];
+        self.endpoints[index].sender.url()
+    }
+}
+
+#[async_trait]
+impl RpcBatchSender for PooledHttpSender {
+    /// Sends the whole batch to one endpoint, failing over on transport
+    /// errors. Errors of individual items are returned as is.
+    async fn send_batch(&self, requests: Vec<BatchRequest>) -> client_error::Result<BatchResponse> {
+        self.with_failover(|sender| {
+            let requests = requests.clone();
+            async move { sender.send_batch(requests).await }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::test_util::refused_url;
+    use solana_client::{
+        client_error::ClientError,
+        rpc_custom_error,
+        rpc_request::{RpcError, RpcResponseErrorData},
+    };
+
+    #[test]
+    fn ranks_healthy_fast_endpoints_first() {
+        let pool = PooledHttpSender::new(&["http://a", "http://b", "http://c"]);
+        {
+            let mut a = pool.endpoints[0].health.write().unwrap();
+            a.record_success(Duration::from_millis(300));
+        }
+        {
+            let mut b = pool.endpoints[1].health.write().unwrap();
+            b.record_success(Duration::from_millis(50));
+        }
+        {
+            let mut c = pool.endpoints[2].health.write().unwrap();
+            c.record_success(Duration::from_millis(10));
+            c.record_failure();
+        }
+        assert_eq!(pool.ranked(), vec![1, 0, 2]);
+        assert_eq!(pool.url(), "http://b");
+
+        pool.endpoints[1].health.write().unwrap().slot_lag = Some(100);
+        assert_eq!(pool.ranked(), vec![0, 1, 2]);
+    }
+
+    #[tokio::test]
+    async fn hands_off_failing_endpoints_without_retrying() {
+        let pool = PooledHttpSender::new(&[refused_url(), refused_url()]);
+
+        let start = Instant::now();
+        let result = pool
+            .send(RpcRequest::GetSlot, serde_json::Value::Null)
+            .await;
+        assert!(is_endpoint_failure(&result.unwrap_err()));
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(pool.health().iter().all(|x| x.failures == 1));
+        assert_eq!(pool.get_transport_stats().request_count, 2);
+
+        let alone = PooledHttpSender::endpoint_retry_policy(1);
+        assert_eq!(alone.max_attempts, RetryPolicy::default().max_attempts);
+    }
+
+    #[test]
+    fn fails_over_on_transport_and_unhealthy_errors() {
+        let io = ClientError::from(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        assert!(is_endpoint_failure(&io));
+
+        let unhealthy = ClientError::from(RpcError::RpcResponseError {
+            code: rpc_custom_error::JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+            message: "Node is behind".into(),
+            data: RpcResponseErrorData::Empty,
+        });
+        assert!(is_endpoint_failure(&unhealthy));
+
+        let invalid = ClientError::from(RpcError::RpcResponseError {
+            code: -32602,
+            message: "Invalid params".into(),
+            data: RpcResponseErrorData::Empty,
+        });
+        assert!(!is_endpoint_failure(&invalid));
+    }
+}
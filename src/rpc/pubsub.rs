@@ -0,0 +1,259 @@
+use futures::stream::{BoxStream, SelectAll, StreamExt};
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_client::{
+    nonblocking::pubsub_client::{PubsubClient, PubsubClientError},
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+    rpc_response::RpcKeyedAccount,
+};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+use std::time::Duration;
+use tokio::{sync::mpsc, task::JoinHandle, time::sleep};
+
+use crate::error::{CrawlerError, Result};
+
+/// Offset of the owner field in token accounts, the same for the legacy
+/// token program and Token-2022.
+pub const TOKEN_ACCOUNT_OWNER_OFFSET: usize = 32;
+
+/// Notifications buffered before the connection waits on the consumer.
+const EVENT_BUFFER: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Subscription {
+    /// `accountSubscribe` to a single account.
+    Account(Pubkey),
+    /// `programSubscribe` to the accounts of `program` whose owner field is
+    /// `owner`, e.g. the token accounts of a wallet.
+    ProgramOwner { program: Pubkey, owner: Pubkey },
+}
+
+#[derive(Clone, Debug)]
+pub enum PubsubEvent {
+    /// Every subscription is in place, after connecting or reconnecting.
+    /// Notifications sent while disconnected are lost.
+    Connected,
+    Account {
+        pubkey: Pubkey,
+        slot: u64,
+        account: UiAccount,
+    },
+    Program {
+        slot: u64,
+        account: RpcKeyedAccount,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PubsubConfig {
+    pub commitment: CommitmentConfig,
+    /// Delay before the first reconnection attempt, doubled on every failed
+    /// attempt.
+    pub reconnect_delay: Duration,
+    pub max_reconnect_delay: Duration,
+}
+
+impl Default for PubsubConfig {
+    fn default() -> Self {
+        Self {
+            commitment: CommitmentConfig::confirmed(),
+            reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl PubsubConfig {
+    fn account_config(&self) -> RpcAccountInfoConfig {
+        RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::JsonParsed),
+            commitment: Some(self.commitment),
+            ..Default::default()
+        }
+    }
+
+    fn program_config(&self, owner: &Pubkey) -> RpcProgramAccountsConfig {
+        RpcProgramAccountsConfig {
+            filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                TOKEN_ACCOUNT_OWNER_OFFSET,
+                owner.as_ref(),
+            ))]),
+            account_config: self.account_config(),
+            with_context: Some(true),
+        }
+    }
+}
+
+/// Websocket url of an http RPC endpoint. An explicit port is bumped by one,
+/// following the validator's default of serving pubsub next to RPC.
+pub fn websocket_url(http_url: &str) -> Result<String> {
+    let invalid = |reason: &str| CrawlerError::InvalidConfig(format!("{}: {}", http_url, reason));
+    let mut url = reqwest::Url::parse(http_url).map_err(|err| invalid(&err.to_string()))?;
+
+    let scheme = match url.scheme() {
+        "http" | "ws" => "ws",
+        "https" | "wss" => "wss",
+        _ => return Err(invalid("expected an http or websocket url")),
+    };
+    if url.scheme() != scheme {
+        url.set_scheme(scheme)
+            .map_err(|_| invalid("cannot switch to a websocket scheme"))?;
+        if let Some(port) = url.port() {
+            url.set_port(Some(port.saturating_add(1)))
+                .map_err(|_| invalid("cannot set the websocket port"))?;
+        }
+    }
+
+    Ok(url.to_string())
+}
+
+/// Live notifications of a fixed set of subscriptions. The connection is
+/// re-established and every subscription renewed whenever it drops. Dropping
+/// the subscription closes the connection.
+pub struct PubsubSubscription {
+    events: mpsc::Receiver<PubsubEvent>,
+    task: JoinHandle<()>,
+}
+
+impl PubsubSubscription {
+    pub fn new<U: ToString>(
+        url: U,
+        subscriptions: Vec<Subscription>,
+        config: PubsubConfig,
+    ) -> Self {
+        let (sender, events) = mpsc::channel(EVENT_BUFFER);
+        let task = tokio::spawn(run(url.to_string(), subscriptions, config, sender));
+        Self { events, task }
+    }
+
+    /// Waits for the next notification.
+    pub async fn recv(&mut self) -> Option<PubsubEvent> {
+        self.events.recv().await
+    }
+
+    /// Next notification if one is already waiting.
+    pub fn try_recv(&mut self) -> Option<PubsubEvent> {
+        self.events.try_recv().ok()
+    }
+}
+
+impl Drop for PubsubSubscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn run(
+    url: String,
+    subscriptions: Vec<Subscription>,
+    config: PubsubConfig,
+    events: mpsc::Sender<PubsubEvent>,
+) {
+    let mut delay = config.reconnect_delay;
+    loop {
+        match listen(&url, &subscriptions, &config, &events).await {
+            Ok(()) => {
+                log::warn!("pubsub: connection to {} closed, reconnecting", url);
+                delay = config.reconnect_delay;
+            }
+            Err(err) => log::warn!("pubsub: {}: {}", url, err),
+        }
+        if events.is_closed() {
+            return;
+        }
+
+        sleep(delay).await;
+        delay = (delay * 2).min(config.max_reconnect_delay);
+    }
+}
+
+/// Subscribes to everything over a new connection and forwards notifications
+/// until it drops.
+async fn listen(
+    url: &str,
+    subscriptions: &[Subscription],
+    config: &PubsubConfig,
+    events: &mpsc::Sender<PubsubEvent>,
+) -> std::result::Result<(), PubsubClientError> {
+    let client = PubsubClient::new(url).await?;
+
+    let mut streams: SelectAll<BoxStream<'_, PubsubEvent>> = SelectAll::new();
+    for subscription in subscriptions {
+        // Subscriptions end with the connection, there is nothing to
+        // unsubscribe from.
+        match *subscription {
+            Subscription::Account(pubkey) => {
+                let (stream, _) = client
+                    .account_subscribe(&pubkey, Some(config.account_config()))
+                    .await?;
+                streams.push(
+                    stream
+                        .map(move |response| PubsubEvent::Account {
+                            pubkey,
+                            slot: response.context.slot,
+                            account: response.value,
+                        })
+                        .boxed(),
+                );
+            }
+            Subscription::ProgramOwner { program, owner } => {
+                let (stream, _) = client
+                    .program_subscribe(&program, Some(config.program_config(&owner)))
+                    .await?;
+                streams.push(
+                    stream
+                        .map(|response| PubsubEvent::Program {
+                            slot: response.context.slot,
+                            account: response.value,
+                        })
+                        .boxed(),
+                );
+            }
+        }
+    }
+
+    if events.send(PubsubEvent::Connected).await.is_err() {
+        return Ok(());
+    }
+    while let Some(event) = streams.next().await {
+        if events.send(event).await.is_err() {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn derives_websocket_urls() {
+        let cases = [
+            (
+                "https://api.mainnet-beta.solana.com",
+                "wss://api.mainnet-beta.solana.com/",
+            ),
+            ("http://127.0.0.1:8899", "ws://127.0.0.1:8900/"),
+            ("wss://rpc.example.com:443/ws", "wss://rpc.example.com/ws"),
+        ];
+        for (http_url, ws_url) in cases {
+            assert_eq!(websocket_url(http_url).unwrap(), ws_url);
+        }
+        assert!(websocket_url("ftp://example.com").is_err());
+        assert!(websocket_url("not a url").is_err());
+    }
+
+    #[test]
+    fn filters_program_accounts_by_owner() {
+        let owner = Pubkey::from_str("4Qkev8aNZcqFNSRhQzwyLMFSsi94jHqE8WNVTJzTP99F").unwrap();
+        let config = PubsubConfig::default().program_config(&owner);
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["filters"][0]["memcmp"]["offset"], 32);
+        assert_eq!(json["filters"][0]["memcmp"]["bytes"], owner.to_string());
+        assert_eq!(json["encoding"], "jsonParsed");
+        assert_eq!(json["commitment"], "confirmed");
+    }
+}
@@ -0,0 +1,204 @@
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock, Weak},
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+use crate::error::{CrawlerError, Result};
+
+/// A valid limit, built by `new` or deserialized through it.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RateLimitFields")]
+pub struct RateLimit {
+    requests_per_second: f64,
+    burst: u32,
+}
+
+#[derive(Deserialize)]
+struct RateLimitFields {
+    requests_per_second: f64,
+    burst: u32,
+}
+
+impl TryFrom<RateLimitFields> for RateLimit {
+    type Error = CrawlerError;
+
+    fn try_from(fields: RateLimitFields) -> Result<Self> {
+        Self::new(fields.requests_per_second, fields.burst)
+    }
+}
+
+impl RateLimit {
+    /// Fails unless `requests_per_second` is positive and finite and `burst`
+    /// at least one.
+    pub fn new(requests_per_second: f64, burst: u32) -> Result<Self> {
+        if !(requests_per_second.is_finite() && requests_per_second > 0.0) {
+            return Err(CrawlerError::InvalidConfig(format!(
+                "rate limit of {} requests per second",
+                requests_per_second
+            )));
+        }
+        if burst == 0 {
+            return Err(CrawlerError::InvalidConfig(
+                "rate limit burst of 0 requests".into(),
+            ));
+        }
+        Ok(Self {
+            requests_per_second,
+            burst,
+        })
+    }
+
+    /// Sustained rate at which tokens are refilled.
+    pub fn requests_per_second(&self) -> f64 {
+        self.requests_per_second
+    }
+
+    /// Requests that may be sent at once after a quiet period.
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+}
+
+struct BucketState {
+    /// Negative when requests are queued waiting for tokens.
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket limiting the requests sent to one endpoint.
+pub struct TokenBucket {
+    limit: RateLimit,
+    state: Mutex<BucketState>,
+}
+
+/// Buckets shared by every sender of the same url, dropped with the last
+/// of them.
+static BUCKETS: OnceLock<Mutex<HashMap<String, Weak<TokenBucket>>>> = OnceLock::new();
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            state: Mutex::new(BucketState {
+                tokens: limit.burst as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Bucket of `url`, shared with every other sender of that url. Fails
+    /// if those senders use a different `limit`, they would otherwise stop
+    /// sharing the budget of the endpoint.
+    pub fn for_url(url: &str, limit: RateLimit) -> Result<Arc<Self>> {
+        let mut buckets = BUCKETS.get_or_init(Default::default).lock().unwrap();
+        buckets.retain(|_, bucket| bucket.strong_count() > 0);
+        if let Some(bucket) = buckets.get(url).and_then(Weak::upgrade) {
+            if bucket.limit != limit {
+                return Err(CrawlerError::InvalidConfig(format!(
+                    "rate limit of {}/s (burst {}) for {} conflicts with {}/s (burst {})",
+                    limit.requests_per_second,
+                    limit.burst,
+                    url,
+                    bucket.limit.requests_per_second,
+                    bucket.limit.burst
+                )));
+            }
+            return Ok(bucket);
+        }
+
+        let bucket = Arc::new(Self::new(limit));
+        buckets.insert(url.to_string(), Arc::downgrade(&bucket));
+        Ok(bucket)
+    }
+
+    pub fn limit(&self) -> RateLimit {
+        self.limit
+    }
+
+    /// Waits for a token and returns how long that took.
+    pub async fn acquire(&self) -> Duration {
+        let wait = self.reserve(Instant::now());
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+        wait
+    }
+
+    /// Takes a token, going into debt if none is left, and returns how long
+    /// the caller has to wait for it. Debt makes concurrent callers queue up
+    /// in order instead of racing for the next token.
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let refill = now.saturating_duration_since(state.updated).as_secs_f64()
+            * self.limit.requests_per_second;
+        state.tokens = (state.tokens + refill).min(self.limit.burst as f64);
+        state.updated = now;
+
+        state.tokens -= 1.0;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / self.limit.requests_per_second)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bursts_then_spaces_requests() {
+        let bucket = TokenBucket::new(RateLimit::new(10.0, 2).unwrap());
+        let start = Instant::now();
+
+        assert_eq!(bucket.reserve(start), Duration::ZERO);
+        assert_eq!(bucket.reserve(start), Duration::ZERO);
+        assert_eq!(bucket.reserve(start), Duration::from_millis(100));
+        assert_eq!(bucket.reserve(start), Duration::from_millis(200));
+
+        // Refilled past the debt and capped at the burst size.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.reserve(later), Duration::ZERO);
+        assert_eq!(bucket.reserve(later), Duration::ZERO);
+        assert_eq!(bucket.reserve(later), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn rejects_invalid_limits() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(RateLimit::new(rate, 1).is_err(), "{}", rate);
+        }
+        assert!(RateLimit::new(1.0, 0).is_err());
+
+        let parse = |json| serde_json::from_value::<RateLimit>(json);
+        assert!(parse(serde_json::json!({"requests_per_second": 0.0, "burst": 1})).is_err());
+        assert!(parse(serde_json::json!({"requests_per_second": 1.0, "burst": 0})).is_err());
+        assert_eq!(
+            parse(serde_json::json!({"requests_per_second": 2.0, "burst": 3})).unwrap(),
+            RateLimit::new(2.0, 3).unwrap()
+        );
+    }
+
+    #[test]
+    fn buckets_are_shared_per_url() {
+        let url = "http://rate-limit-test";
+        let limit = RateLimit::new(5.0, 5).unwrap();
+        let a = TokenBucket::for_url(url, limit).unwrap();
+        let b = TokenBucket::for_url(url, limit).unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+
+        let other = TokenBucket::for_url("http://rate-limit-test-other", limit).unwrap();
+        assert!(!Arc::ptr_eq(&a, &other));
+
+        let changed = RateLimit::new(1.0, 1).unwrap();
+        assert!(TokenBucket::for_url(url, changed).is_err());
+
+        // Once its senders are gone the bucket is dropped with its limit.
+        drop((a, b));
+        assert_eq!(TokenBucket::for_url(url, changed).unwrap().limit(), changed);
+    }
+}
@@ -0,0 +1,198 @@
+use rand::Rng;
+use reqwest::StatusCode;
+use std::time::Duration;
+
+/// Transport failures that can be retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RetryableError {
+    Timeout,
+    Connect,
+    /// The request failed while being sent, e.g. a connection reset.
+    Request,
+    /// The response body could not be read.
+    Body,
+}
+
+impl RetryableError {
+    fn matches(&self, err: &reqwest::Error) -> bool {
+        match self {
+            Self::Timeout => err.is_timeout(),
+            Self::Connect => err.is_connect(),
+            Self::Request => err.is_request(),
+            Self::Body => err.is_body() || err.is_decode(),
+        }
+    }
+}
+
+/// How `CustomHttpSender` retries failed requests. Delays grow as
+/// `base_delay * 2^(attempt - 1)`, capped at `max_delay`, and are spread by
+/// `jitter` in both directions.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Attempts including the first one; `1` disables retries.
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// Fraction of the delay added or removed at random, between 0 and 1.
+    pub jitter: f64,
+    pub retryable_statuses: Vec<StatusCode>,
+    pub retryable_errors: Vec<RetryableError>,
+    /// No retry is started once this much time has passed since the first
+    /// attempt.
+    pub time_budget: Duration,
+}
+
+impl RetryPolicy {
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retryable_statuses.contains(&status)
+    }
+
+    pub fn is_retryable_error(&self, err: &reqwest::Error) -> bool {
+        self.retryable_errors.iter().any(|x| x.matches(err))
+    }
+
+    /// Delay before retrying a request that failed with `err`.
+    pub fn backoff_for_error(
+        &self,
+        attempt: u32,
+        elapsed: Duration,
+        err: &reqwest::Error,
+    ) -> Option<Duration> {
+        self.backoff(attempt, elapsed, None)
+            .filter(|_| self.is_retryable_error(err))
+    }
+
+    /// Delay before retrying a request answered with `status`.
+    pub fn backoff_for_status(
+        &self,
+        attempt: u32,
+        elapsed: Duration,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    ) -> Option<Duration> {
+        self.backoff(attempt, elapsed, retry_after)
+            .filter(|_| self.is_retryable_status(status))
+    }
+
+    /// Delay before the attempt following `attempt`, or `None` once the
+    /// attempts or the time budget are used up. A server provided
+    /// `retry_after` replaces the computed backoff.
+    pub fn backoff(
+        &self,
+        attempt: u32,
+        elapsed: Duration,
+        retry_after: Option<Duration>,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+
+        let delay = match retry_after {
+            Some(retry_after) => retry_after.min(self.max_delay),
+            None => {
+                let exponent = attempt.saturating_sub(1).min(31);
+                let delay = self.base_delay.saturating_mul(1 << exponent);
+                self.apply_jitter(delay.min(self.max_delay))
+            }
+        };
+
+        if elapsed + delay > self.time_budget {
+            return None;
+        }
+
+        Some(delay)
+    }
+
+    fn apply_jitter(&self, delay: Duration) -> Duration {
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return delay;
+        }
+
+        let factor = rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter);
+        delay.mul_f64(factor)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 6,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            jitter: 0.2,
+            retryable_statuses: vec![
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            retryable_errors: vec![
+                RetryableError::Timeout,
+                RetryableError::Connect,
+                RetryableError::Request,
+            ],
+            time_budget: Duration::from_secs(60),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exponential_backoff_within_limits() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: 0.0,
+            time_budget: Duration::from_secs(1),
+            ..Default::default()
+        };
+
+        let elapsed = Duration::ZERO;
+        assert_eq!(
+            policy.backoff(1, elapsed, None),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            policy.backoff(2, elapsed, None),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(
+            policy.backoff(4, elapsed, None),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(policy.backoff(5, elapsed, None), None);
+
+        assert_eq!(
+            policy.backoff(1, elapsed, Some(Duration::from_secs(30))),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(policy.backoff(1, Duration::from_millis(950), None), None);
+        assert_eq!(RetryPolicy::none().backoff(1, elapsed, None), None);
+    }
+
+    #[test]
+    fn jitter_stays_in_range() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..Default::default()
+        };
+        for _ in 0..100 {
+            let delay = policy.backoff(1, Duration::ZERO, None).unwrap();
+            assert!(delay >= Duration::from_millis(250));
+            assert!(delay <= Duration::from_millis(750));
+        }
+    }
+}
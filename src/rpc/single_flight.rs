@@ -0,0 +1,204 @@
+use futures::future::{BoxFuture, FutureExt, Shared};
+use solana_client::{
+    client_error::{self, ClientError, ClientErrorKind},
+    rpc_request::{RpcError, RpcResponseErrorData},
+};
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    sync::{Arc, Mutex},
+};
+
+/// Method and params of a call.
+type CallKey = (String, String);
+
+type SharedCall = Shared<BoxFuture<'static, Result<serde_json::Value, Arc<ClientError>>>>;
+
+/// Lets concurrent identical calls share one upstream request. Clones share
+/// the calls in flight.
+#[derive(Clone, Default)]
+pub struct SingleFlight {
+    in_flight: Arc<Mutex<HashMap<CallKey, SharedCall>>>,
+}
+
+impl SingleFlight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of distinct calls currently in flight.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    /// Runs `call` unless an identical call is already in flight, in which
+    /// case its result is awaited instead.
+    pub async fn run<F>(
+        &self,
+        method: String,
+        params: &serde_json::Value,
+        call: F,
+    ) -> client_error::Result<serde_json::Value>
+    where
+        F: Future<Output = client_error::Result<serde_json::Value>> + Send + 'static,
+    {
+        let key = (method, params.to_string());
+        let shared = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(&key) {
+                Some(shared) => shared.clone(),
+                None => {
+                    // The call runs on its own task and removes itself once
+                    // done, even if every caller waiting on it was dropped,
+                    // so later callers never join a call nobody polls.
+                    let guard = Forget {
+                        calls: self.in_flight.clone(),
+                        key: key.clone(),
+                    };
+                    let task = tokio::spawn(async move {
+                        let _guard = guard;
+                        call.await.map_err(Arc::new)
+                    });
+                    let shared = async move {
+                        task.await.unwrap_or_else(|err| {
+                            let kind = ClientErrorKind::Custom(format!("rpc call failed: {}", err));
+                            Err(Arc::new(kind.into()))
+                        })
+                    }
+                    .boxed()
+                    .shared();
+                    in_flight.insert(key, shared.clone());
+                    shared
+                }
+            }
+        };
+
+        shared.await.map_err(|err| share_error(&err))
+    }
+}
+
+/// Removes a call from the calls in flight when dropped.
+struct Forget {
+    calls: Arc<Mutex<HashMap<CallKey, SharedCall>>>,
+    key: CallKey,
+}
+
+impl Drop for Forget {
+    fn drop(&mut self) {
+        self.calls.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Copy of `err` for one of the callers sharing it. `ClientError` isn't
+/// `Clone`, so errors without a cloneable payload are rebuilt from their
+/// message, keeping transport errors as transport errors.
+fn share_error(err: &ClientError) -> ClientError {
+    let kind = match err.kind() {
+        ClientErrorKind::Io(io_err) => io::Error::new(io_err.kind(), io_err.to_string()).into(),
+        ClientErrorKind::Reqwest(reqwest_err) => io::Error::other(reqwest_err.to_string()).into(),
+        ClientErrorKind::RpcError(rpc_err) => ClientErrorKind::RpcError(match rpc_err {
+            RpcError::RpcRequestError(message) => RpcError::RpcRequestError(message.clone()),
+            RpcError::RpcResponseError {
+                code,
+                message,
+                data,
+            } => RpcError::RpcResponseError {
+                code: *code,
+                message: message.clone(),
+                data: match data {
+                    RpcResponseErrorData::Empty => RpcResponseErrorData::Empty,
+                    RpcResponseErrorData::SendTransactionPreflightFailure(result) => {
+                        RpcResponseErrorData::SendTransactionPreflightFailure(result.clone())
+                    }
+                    RpcResponseErrorData::NodeUnhealthy { num_slots_behind } => {
+                        RpcResponseErrorData::NodeUnhealthy {
+                            num_slots_behind: *num_slots_behind,
+                        }
+                    }
+                },
+            },
+            RpcError::ParseError(message) => RpcError::ParseError(message.clone()),
+            RpcError::ForUser(message) => RpcError::ForUser(message.clone()),
+        }),
+        ClientErrorKind::TransactionError(tx_err) => tx_err.clone().into(),
+        kind => ClientErrorKind::Custom(kind.to_string()),
+    };
+
+    match err.request() {
+        Some(request) => ClientError::new_with_request(kind, *request),
+        None => kind.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn coalesces_identical_calls() {
+        let single_flight = SingleFlight::new();
+        let upstream = Arc::new(AtomicU64::new(0));
+
+        let call = |params: serde_json::Value, fail: bool| {
+            let upstream = upstream.clone();
+            let single_flight = single_flight.clone();
+            async move {
+                let call = async move {
+                    upstream.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    if fail {
+                        Err(RpcError::RpcResponseError {
+                            code: -32005,
+                            message: "Node is behind".into(),
+                            data: RpcResponseErrorData::NodeUnhealthy {
+                                num_slots_behind: Some(3),
+                            },
+                        }
+                        .into())
+                    } else {
+                        Ok(serde_json::json!(42))
+                    }
+                };
+                single_flight.run("getSlot".into(), &params, call).await
+            }
+        };
+
+        let params = serde_json::json!([{"commitment": "confirmed"}]);
+        let results = futures::future::join_all((0..5).map(|_| call(params.clone(), false))).await;
+        assert!(results.iter().all(|x| x.as_ref().unwrap() == 42));
+        assert_eq!(upstream.load(Ordering::SeqCst), 1);
+        assert_eq!(single_flight.in_flight(), 0);
+
+        let other = serde_json::json!([{"commitment": "finalized"}]);
+        let errors = futures::future::join_all((0..3).map(|_| call(other.clone(), true))).await;
+        assert_eq!(upstream.load(Ordering::SeqCst), 2);
+        for err in errors {
+            assert!(matches!(
+                err.unwrap_err().kind(),
+                ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                    code: -32005,
+                    data: RpcResponseErrorData::NodeUnhealthy {
+                        num_slots_behind: Some(3)
+                    },
+                    ..
+                })
+            ));
+        }
+
+        // Done calls are forgotten, the next one goes upstream again.
+        call(params.clone(), false).await.unwrap();
+        assert_eq!(upstream.load(Ordering::SeqCst), 3);
+
+        // A call whose callers all gave up still finishes and is forgotten.
+        let abandoned =
+            tokio::time::timeout(Duration::from_millis(10), call(params.clone(), false));
+        assert!(abandoned.await.is_err());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(single_flight.in_flight(), 0);
+        call(params, false).await.unwrap();
+        assert_eq!(upstream.load(Ordering::SeqCst), 5);
+    }
+}
@@ -0,0 +1,8 @@
+//! Helpers shared by the sender tests.
+
+/// Url of a local port nothing listens on. The port is released as soon as
+/// it is picked, so connections to it are refused.
+pub fn refused_url() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}